@@ -0,0 +1,263 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::determinism::SimulationRng;
+use crate::input::{InputSource, PlayerInput};
+use crate::pitch::{Goal, PitchGeometry, GOAL_AREA_DEPTH};
+use crate::simulation::{
+    apply_friction, Ball, MatchConfig, Radius, Role, Team, Velocity, KICK_MARGIN,
+};
+
+// The AI guesses where the ball will be this many steps ahead.
+pub const AI_ANTICIPATION_TICKS: f32 = 8.0;
+// The AI only shoots when the ball lies this well in the direction of its
+// aim, as the cosine of the angle between them.
+pub const AI_SHOT_ALIGNMENT: f32 = 0.9;
+// The AI stops moving when it is this close to where it wants to be.
+pub const AI_ARRIVAL_DISTANCE: f32 = 4.0;
+// How well the AI plays with the teammates of human players.
+pub const TEAMMATE_DIFFICULTY: Difficulty = Difficulty::Normal;
+// How many steps ahead a goalkeeper follows the path of the ball.
+pub const AI_PREDICTION_TICKS: usize = 120;
+// The AI charges a kick while the ball is this many times its reach away.
+pub const AI_CHARGE_DISTANCE: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    // How many steps late the AI sees the ball.
+    pub fn reaction_delay(self) -> usize {
+        match self {
+            Difficulty::Easy => 24,
+            Difficulty::Normal => 12,
+            Difficulty::Hard => 4,
+        }
+    }
+
+    // How far from the middle of the goal the AI may aim, in halves of the
+    // goal width. Above 1 it can miss the goal.
+    pub fn aim_error(self) -> f32 {
+        match self {
+            Difficulty::Easy => 1.4,
+            Difficulty::Normal => 0.7,
+            Difficulty::Hard => 0.25,
+        }
+    }
+}
+
+// What an AI controlled player remembers between steps. Every player has it,
+// so any of them can be handed to the AI.
+#[derive(Component, Debug, Clone)]
+pub struct AiMemory {
+    // Position and velocity of the ball in the last steps, the oldest first.
+    seen: VecDeque<(Vec2, Vec2)>,
+    // Where the next shot goes, relative to the middle of the goal, in halves
+    // of the goal width.
+    aim: f32,
+    // The AI's own random numbers: a replay doesn't run the AI, so it must
+    // not use the SimulationRng.
+    rng: SimulationRng,
+}
+
+impl AiMemory {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seen: VecDeque::new(),
+            aim: 0.0,
+            rng: SimulationRng::new(seed),
+        }
+    }
+
+    // Remembers the ball and returns it as the AI sees it, i.e. as it was
+    // delay steps ago (or as early as remembered).
+    fn see(&mut self, ball: (Vec2, Vec2), delay: usize) -> (Vec2, Vec2) {
+        self.seen.push_back(ball);
+        while self.seen.len() > delay + 1 {
+            self.seen.pop_front();
+        }
+        self.seen[0]
+    }
+
+    fn pick_aim(&mut self, error: f32) {
+        self.aim = (self.rng.next_f32() * 2.0 - 1.0) * error;
+    }
+}
+
+// Where an outfield player goes and whether it kicks. It gets behind the
+// ball and pushes it towards the aim, or first runs back to the goal side of
+// the ball when the ball is behind it.
+fn outfield_decision(
+    geometry: &PitchGeometry,
+    own_goal: &Goal,
+    aim: Vec2,
+    position: Vec2,
+    ball: Vec2,
+    reach: f32,
+) -> (Vec2, bool) {
+    let shot = (aim - ball).normalize_or_zero();
+    let defending = (own_goal.centre() - ball).normalize_or_zero();
+    if (position - ball).dot(defending) < 0.0 {
+        // The ball is between the player and its own goal. Run back past it
+        // on the side the player is on, not through it.
+        let mut side = defending.perp();
+        if side.dot(position - ball) < 0.0 {
+            side = -side;
+        }
+        return (ball + (defending + side) * reach * 2.0, false);
+    }
+
+    // Next to a wall there may be no room behind the ball, so there the AI
+    // kicks it anywhere but towards its own goal, to get it off the wall.
+    let to_ball = ball - position;
+    let direction = to_ball.normalize_or_zero();
+    let at_wall = geometry
+        .walls
+        .iter()
+        .any(|wall| wall.closest_point(ball).distance(ball) < reach);
+    if to_ball.length() <= reach * 1.5
+        && (direction.dot(shot) >= AI_SHOT_ALIGNMENT || (at_wall && direction.dot(defending) < 0.0))
+    {
+        (ball, true)
+    } else {
+        (ball - shot * reach, false)
+    }
+}
+
+// Where a goalkeeper goes and whether it kicks. It follows the path of the
+// ball, slowing down as in control_ball_velocity, and meets the ball where
+// the path through the goal area is the closest. When the ball isn't coming,
+// it waits between the ball and the middle of the goal. It never leaves the
+// goal area.
+fn goalkeeper_decision(
+    own_goal: &Goal,
+    friction: f32,
+    position: Vec2,
+    (ball, velocity): (Vec2, Vec2),
+    radius: f32,
+    reach: f32,
+) -> (Vec2, bool) {
+    let to_ball = ball - position;
+    if to_ball.length() <= reach * 1.5 && to_ball.normalize_or_zero().dot(own_goal.inward()) > 0.0 {
+        // Clear the ball away from the goal.
+        return (own_goal.clamp_to_area(ball, radius), true);
+    }
+
+    // The point of the path of the ball in the goal area that is the closest
+    // to the goalkeeper.
+    let mut intercept: Option<Vec2> = None;
+    let (mut predicted, mut velocity) = (ball, velocity);
+    for _ in 0..AI_PREDICTION_TICKS {
+        if own_goal.in_area(predicted)
+            && intercept
+                .is_none_or(|intercept| predicted.distance(position) < intercept.distance(position))
+        {
+            intercept = Some(predicted);
+        }
+        if own_goal.is_scored(predicted) || velocity == Vec2::ZERO {
+            break;
+        }
+        predicted += velocity;
+        velocity = Vec2::new(
+            apply_friction(velocity.x, friction),
+            apply_friction(velocity.y, friction),
+        );
+    }
+    if let Some(intercept) = intercept {
+        return (own_goal.clamp_to_area(intercept, radius), false);
+    }
+
+    let centre = own_goal.centre();
+    let guard = centre + (ball - centre).normalize_or_zero() * GOAL_AREA_DEPTH / 4.0;
+    (own_goal.clamp_to_area(guard, radius), false)
+}
+
+// Fills PlayerInput of the AI controlled players, as outfield players or
+// goalkeepers.
+pub fn ai_input_system(
+    config: Res<MatchConfig>,
+    geometry: Res<PitchGeometry>,
+    query_ball: Query<(&Transform, &Velocity, &Radius), With<Ball>>,
+    mut query: Query<(
+        &InputSource,
+        &Team,
+        &Role,
+        &Transform,
+        &Radius,
+        &mut PlayerInput,
+        &mut AiMemory,
+    )>,
+) {
+    let (ball, ball_radius) = match query_ball.iter().next() {
+        Some((transform, velocity, radius)) => (
+            (
+                transform.translation.truncate(),
+                Vec2::new(velocity.x, velocity.y),
+            ),
+            radius.0,
+        ),
+        None => return,
+    };
+
+    for (source, team, role, transform, radius, mut input, mut memory) in query.iter_mut() {
+        let difficulty = match source {
+            InputSource::Ai(difficulty) => *difficulty,
+            _ => continue,
+        };
+        let goal = |team: Team| geometry.goals.iter().find(|goal| goal.defending == team);
+        let (own_goal, target_goal) = match (goal(*team), goal(team.opponent())) {
+            (Some(own_goal), Some(target_goal)) => (own_goal, target_goal),
+            _ => continue,
+        };
+
+        let seen = memory.see(ball, difficulty.reaction_delay());
+        let position = transform.translation.truncate();
+        let reach = radius.0 + ball_radius;
+        let (destination, kick) = match role {
+            Role::Outfield => {
+                let half_goal = (target_goal.line.b - target_goal.line.a) / 2.0;
+                let aim = target_goal.centre() + half_goal * memory.aim;
+                let (seen_position, seen_velocity) = seen;
+                let ball_position = seen_position + seen_velocity * AI_ANTICIPATION_TICKS;
+                outfield_decision(&geometry, own_goal, aim, position, ball_position, reach)
+            }
+            Role::Goalkeeper => goalkeeper_decision(
+                own_goal,
+                config.physics.ball_friction,
+                position,
+                seen,
+                radius.0,
+                reach,
+            ),
+        };
+
+        // A kick is taken by releasing kick, so the AI holds it when the ball
+        // is near and lets go when it wants to kick and the ball is in reach.
+        // If it wasn't holding kick yet, it presses it now and kicks in the
+        // next step.
+        let charging = input.kick;
+        let in_reach = position.distance(ball.0) < reach + KICK_MARGIN;
+        let release = kick && in_reach && charging;
+        if release {
+            memory.pick_aim(difficulty.aim_error());
+        }
+        let near_ball = position.distance(seen.0) <= reach * AI_CHARGE_DISTANCE;
+        let to_destination = destination - position;
+        *input = PlayerInput {
+            movement: if to_destination.length() > AI_ARRIVAL_DISTANCE {
+                to_destination.normalize_or_zero()
+            } else {
+                Vec2::ZERO
+            },
+            kick: !release && (kick || near_ball),
+            switch: false,
+        };
+    }
+}
@@ -0,0 +1,412 @@
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+
+use crate::simulation::{Mass, PreviousPosition, Radius, Restitution, Velocity};
+
+// One side of a collision.
+pub struct CollisionBody<'a> {
+    pub velocity: &'a mut Velocity,
+    pub transform: &'a mut Transform,
+    pub radius: f32,
+    pub mass: Mass,
+    pub restitution: Restitution,
+}
+
+// Calculates new velocity vectors after collision.
+// Using some math formulas from the internet.
+// Inspired with: https://stackoverflow.com/questions/345838/ball-to-ball-collision-detection-and-handling
+pub fn handle_collision(body1: CollisionBody, body2: CollisionBody) {
+    let im1 = body1.mass.inverse();
+    let im2 = body2.mass.inverse();
+    // Two static bodies never move.
+    if im1 + im2 == 0.0 {
+        return;
+    }
+
+    let delta = (body1.transform.translation - body2.transform.translation).truncate();
+    let d = delta.length();
+    if d == 0.0 {
+        return;
+    }
+    let normal = delta / d;
+    // Minimum translation distance that pushes the bodies apart. Zero when
+    // they just touch, e.g. after a swept collision.
+    let mtd = normal * (body1.radius + body2.radius - d).max(0.0);
+
+    body1.transform.translation.x += mtd[0] * (im1 / (im1 + im2));
+    body1.transform.translation.y += mtd[1] * (im1 / (im1 + im2));
+
+    body2.transform.translation.x -= mtd[0] * (im2 / (im1 + im2));
+    body2.transform.translation.y -= mtd[1] * (im2 / (im1 + im2));
+
+    let v = Vec2::new(
+        body1.velocity.x - body2.velocity.x,
+        body1.velocity.y - body2.velocity.y,
+    );
+    let vn = v.dot(normal);
+
+    if vn > 0.0 {
+        return;
+    }
+
+    let restitution = body1.restitution.0.max(body2.restitution.0);
+    let i = (-(1.0 + restitution) * vn) / (im1 + im2);
+    let impulse = normal * i;
+
+    body1.velocity.x += impulse[0] * im1;
+    body1.velocity.y += impulse[1] * im1;
+
+    body2.velocity.x -= impulse[0] * im2;
+    body2.velocity.y -= impulse[1] * im2;
+}
+
+// Time of impact of two circles moving in straight lines: the fraction of
+// the step (0 to 1) after which the distance of their centres first equals
+// radius. None when they don't meet during the step, move apart or already
+// overlap at the start (the overlap is then resolved without sweeping).
+pub fn sweep_circles(
+    start1: Vec2,
+    motion1: Vec2,
+    start2: Vec2,
+    motion2: Vec2,
+    radius: f32,
+) -> Option<f32> {
+    // Solve |start + t * motion| = radius in the frame of the second circle.
+    let start = start1 - start2;
+    let motion = motion1 - motion2;
+    let a = motion.dot(motion);
+    let b = 2.0 * start.dot(motion);
+    let c = start.dot(start) - radius * radius;
+    if c <= 0.0 || a == 0.0 || b >= 0.0 {
+        return None;
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let t = (-b - discriminant.sqrt()) / (2.0 * a);
+    if t <= 1.0 {
+        Some(t.max(0.0))
+    } else {
+        None
+    }
+}
+
+// Time of impact of a circle moving in a straight line with the segment from
+// a to b, together with the normal of the segment at the point of impact
+// (pointing towards the circle). Same rules as in sweep_circles.
+pub fn sweep_circle_segment(
+    start: Vec2,
+    motion: Vec2,
+    radius: f32,
+    a: Vec2,
+    b: Vec2,
+) -> Option<(f32, Vec2)> {
+    let mut hit: Option<(f32, Vec2)> = None;
+
+    // The side of the segment.
+    let edge = b - a;
+    let mut normal = edge.perp().normalize_or_zero();
+    if normal.dot(start - a) < 0.0 {
+        normal = -normal;
+    }
+    let distance = normal.dot(start - a);
+    let approach = -normal.dot(motion);
+    let on_segment = |point: Vec2| {
+        let u = (point - a).dot(edge) / edge.length_squared();
+        (0.0..=1.0).contains(&u)
+    };
+    if distance < radius && on_segment(start) {
+        // Already touching.
+        return None;
+    }
+    if distance >= radius && approach > 0.0 {
+        let t = (distance - radius) / approach;
+        if t <= 1.0 && on_segment(start + motion * t) {
+            hit = Some((t, normal));
+        }
+    }
+
+    // The ends of the segment.
+    for end in [a, b] {
+        if let Some(t) = sweep_circles(start, motion, end, Vec2::ZERO, radius) {
+            if hit.is_none_or(|(t_hit, _)| t < t_hit) {
+                let normal = (start + motion * t - end).normalize_or_zero();
+                hit = Some((t, normal));
+            }
+        }
+    }
+
+    hit
+}
+
+// Axis aligned bounding box of a body, used by the broadphase.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Bounds {
+    pub fn circle(center: Vec2, radius: f32) -> Self {
+        Self {
+            min: center - Vec2::splat(radius),
+            max: center + Vec2::splat(radius),
+        }
+    }
+
+    pub fn union(self, other: Bounds) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+}
+
+// Sweep and prune: finds the pairs of bounds that overlap, without testing
+// every pair. The bounds have to be sorted by min.x. Pairs are returned as
+// (i, j) with i < j, in a deterministic order.
+pub fn sweep_and_prune(bounds: &[Bounds]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    let mut active: Vec<usize> = Vec::new();
+
+    for (i, current) in bounds.iter().enumerate() {
+        active.retain(|&j| bounds[j].max.x >= current.min.x);
+        for &j in active.iter() {
+            if bounds[j].min.y <= current.max.y && current.min.y <= bounds[j].max.y {
+                pairs.push((j, i));
+            }
+        }
+        active.push(i);
+    }
+
+    pairs
+}
+
+// Everything the collision system needs to know about a body.
+type BodyQuery<'a> = (
+    &'a mut Velocity,
+    &'a mut Transform,
+    &'a Radius,
+    Option<&'a PreviousPosition>,
+    Option<&'a Mass>,
+    Option<&'a Restitution>,
+);
+
+// Resolves contacts between all bodies with a Radius. Bodies that overlap at
+// the end of the step are pushed apart. Fast bodies that passed through each
+// other during the step are moved back to where they first touched, collide
+// there and then move on for the rest of the step.
+pub fn collision_system(mut query: Query<BodyQuery>) {
+    let mut bodies: Vec<_> = query
+        .iter_mut()
+        .map(|body| {
+            let (_, transform, radius, previous, ..) = &body;
+            let end = transform.translation.truncate();
+            let start = previous.map_or(end, |previous| previous.0.truncate());
+            // Covers the whole path of the body in this step.
+            let bounds = Bounds::circle(start, radius.0).union(Bounds::circle(end, radius.0));
+            (bounds, start, body)
+        })
+        .collect();
+    // The order of the query depends on how the entities were spawned, so
+    // sort the bodies by their state instead, to resolve the contacts in the
+    // same order on every run.
+    bodies.sort_by(|(bounds1, _, body1), (bounds2, _, body2)| {
+        let key = |bounds: &Bounds, velocity: &Velocity, radius: &Radius| {
+            [bounds.min.x, bounds.min.y, radius.0, velocity.x, velocity.y]
+        };
+        key(bounds1, &body1.0, body1.2)
+            .iter()
+            .zip(key(bounds2, &body2.0, body2.2).iter())
+            .map(|(a, b)| a.total_cmp(b))
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let bounds: Vec<_> = bodies.iter().map(|(bounds, ..)| *bounds).collect();
+
+    for (i, j) in sweep_and_prune(&bounds) {
+        let (head, tail) = bodies.split_at_mut(j);
+        let (_, start1, (velocity1, transform1, radius1, _, mass1, restitution1)) = &mut head[i];
+        let (_, start2, (velocity2, transform2, radius2, _, mass2, restitution2)) = &mut tail[0];
+        let radius = radius1.0 + radius2.0;
+
+        let end1 = transform1.translation.truncate();
+        let end2 = transform2.translation.truncate();
+        let time_of_impact = if end1.distance(end2) < radius {
+            None
+        } else {
+            match sweep_circles(*start1, end1 - *start1, *start2, end2 - *start2, radius) {
+                Some(t) => Some(t),
+                None => continue,
+            }
+        };
+
+        if let Some(t) = time_of_impact {
+            // Rewind both bodies to the point of impact.
+            let contact1 = start1.lerp(end1, t);
+            let contact2 = start2.lerp(end2, t);
+            transform1.translation.x = contact1.x;
+            transform1.translation.y = contact1.y;
+            transform2.translation.x = contact2.x;
+            transform2.translation.y = contact2.y;
+        }
+
+        handle_collision(
+            CollisionBody {
+                velocity: velocity1,
+                transform: transform1,
+                radius: radius1.0,
+                mass: mass1.copied().unwrap_or_default(),
+                restitution: restitution1.copied().unwrap_or_default(),
+            },
+            CollisionBody {
+                velocity: velocity2,
+                transform: transform2,
+                radius: radius2.0,
+                mass: mass2.copied().unwrap_or_default(),
+                restitution: restitution2.copied().unwrap_or_default(),
+            },
+        );
+
+        if let Some(t) = time_of_impact {
+            // Move on with the new velocities for the rest of the step.
+            for (velocity, transform) in [(velocity1, transform1), (velocity2, transform2)] {
+                transform.translation.x += velocity.x * (1.0 - t);
+                transform.translation.y += velocity.y * (1.0 - t);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::determinism::SimulationRng;
+
+    const EPSILON: f32 = 1e-4;
+
+    #[test]
+    fn head_on_circles_are_swept() {
+        let (start1, motion1) = (Vec2::new(-50.0, 0.0), Vec2::new(200.0, 0.0));
+        let (start2, motion2) = (Vec2::new(50.0, 0.0), Vec2::new(-200.0, 0.0));
+        // At the end of the step they are far apart again, only the sweep
+        // notices that they met.
+        assert!((start1 + motion1).distance(start2 + motion2) > 20.0);
+
+        let t = sweep_circles(start1, motion1, start2, motion2, 20.0).expect("no impact");
+        assert!(t > 0.0 && t < 1.0);
+        assert!((t - 0.2).abs() < EPSILON);
+    }
+
+    #[test]
+    fn grazing_circles_touch_or_miss() {
+        let motion = Vec2::new(200.0, 0.0);
+        let touch = sweep_circles(
+            Vec2::new(-100.0, 19.99),
+            motion,
+            Vec2::ZERO,
+            Vec2::ZERO,
+            20.0,
+        );
+        let miss = sweep_circles(
+            Vec2::new(-100.0, 20.01),
+            motion,
+            Vec2::ZERO,
+            Vec2::ZERO,
+            20.0,
+        );
+        assert!(touch.is_some_and(|t| (t - 0.5).abs() < 0.01));
+        assert_eq!(miss, None);
+    }
+
+    #[test]
+    fn circles_moving_apart_dont_collide() {
+        let t = sweep_circles(
+            Vec2::new(-30.0, 0.0),
+            Vec2::new(-10.0, 0.0),
+            Vec2::new(30.0, 0.0),
+            Vec2::new(10.0, 0.0),
+            20.0,
+        );
+        assert_eq!(t, None);
+    }
+
+    #[test]
+    fn overlapping_circles_are_not_swept() {
+        let t = sweep_circles(
+            Vec2::new(-5.0, 0.0),
+            Vec2::new(10.0, 0.0),
+            Vec2::new(5.0, 0.0),
+            Vec2::ZERO,
+            20.0,
+        );
+        assert_eq!(t, None);
+    }
+
+    #[test]
+    fn circle_hits_face_of_segment() {
+        let (t, normal) = sweep_circle_segment(
+            Vec2::new(0.0, 50.0),
+            Vec2::new(0.0, -100.0),
+            10.0,
+            Vec2::new(-50.0, 0.0),
+            Vec2::new(50.0, 0.0),
+        )
+        .expect("no impact");
+        assert!((t - 0.4).abs() < EPSILON);
+        assert!(normal.distance(Vec2::Y) < EPSILON);
+    }
+
+    #[test]
+    fn circle_hits_end_of_segment() {
+        // Passes the end of the segment 5 away from its centre line.
+        let (t, normal) = sweep_circle_segment(
+            Vec2::new(55.0, 50.0),
+            Vec2::new(0.0, -100.0),
+            10.0,
+            Vec2::new(-50.0, 0.0),
+            Vec2::new(50.0, 0.0),
+        )
+        .expect("no impact");
+        let height = 75.0f32.sqrt();
+        assert!((t - (50.0 - height) / 100.0).abs() < EPSILON);
+        assert!(normal.distance(Vec2::new(5.0, height) / 10.0) < EPSILON);
+    }
+
+    #[test]
+    fn sweep_and_prune_finds_the_same_pairs_as_brute_force() {
+        // Hundreds of bodies of different sizes, many of them touching.
+        let mut rng = SimulationRng::new(9);
+        let mut bounds: Vec<_> = (0..500)
+            .map(|_| {
+                let center = Vec2::new(rng.next_f32() * 1000.0, rng.next_f32() * 800.0);
+                Bounds::circle(center, 5.0 + rng.next_f32() * 25.0)
+            })
+            .collect();
+        bounds.sort_by(|bounds1, bounds2| bounds1.min.x.total_cmp(&bounds2.min.x));
+
+        let overlap = |b1: &Bounds, b2: &Bounds| {
+            b1.min.x <= b2.max.x
+                && b2.min.x <= b1.max.x
+                && b1.min.y <= b2.max.y
+                && b2.min.y <= b1.max.y
+        };
+        let mut expected = Vec::new();
+        for i in 0..bounds.len() {
+            for j in i + 1..bounds.len() {
+                if overlap(&bounds[i], &bounds[j]) {
+                    expected.push((i, j));
+                }
+            }
+        }
+
+        let mut pairs = sweep_and_prune(&bounds);
+        assert!(pairs.iter().all(|(i, j)| i < j));
+        pairs.sort_unstable();
+        assert!(!expected.is_empty());
+        assert_eq!(pairs, expected);
+    }
+}
@@ -0,0 +1,122 @@
+use bevy::prelude::*;
+
+use crate::rules::{Kickoff, KickoffStage, MatchClock, MatchPhase};
+use crate::simulation::{Ball, KickCharge, PlayerId, Score, SimulationTick, Team, Velocity};
+
+// Random numbers for the simulation, seeded from MatchConfig. Anything random
+// in gameplay must come from here (never from thread_rng or the clock), so a
+// match can be replayed from its seed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationRng {
+    state: u64,
+}
+
+impl SimulationRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    // SplitMix64, small and the same on every platform.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniformly distributed in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// 64-bit FNV-1a. Unlike DefaultHasher it is specified, so hashes can be
+// stored and compared between builds and machines.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xCBF2_9CE4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_f32(&mut self, value: f32) {
+        self.write(&value.to_bits().to_le_bytes());
+    }
+}
+
+// Hash of everything that determines how the match continues: the step
+// counter, the score, the random number generator, the phase, clock and
+// kickoff of the match, the position and velocity of every body and the kicks
+// being charged. Two runs fed with the same seed and inputs have the same
+// hash after every step.
+pub fn state_hash(world: &mut World) -> u64 {
+    let mut hasher = Fnv1a::new();
+
+    let tick = world.get_resource::<SimulationTick>().copied();
+    hasher.write_u64(tick.map_or(0, |tick| tick.0));
+    if let Some(score) = world.get_resource::<Score>() {
+        hasher.write_u64(score.red as u64);
+        hasher.write_u64(score.blue as u64);
+    }
+    if let Some(rng) = world.get_resource::<SimulationRng>() {
+        hasher.write_u64(rng.state);
+    }
+    if let Some(phase) = world.get_resource::<MatchPhase>() {
+        hasher.write_u64(*phase as u64);
+    }
+    if let Some(clock) = world.get_resource::<MatchClock>() {
+        hasher.write_u64(clock.elapsed);
+        hasher.write_u64(clock.half as u64);
+        hasher.write_u64(clock.break_left);
+    }
+    if let Some(kickoff) = world.get_resource::<Kickoff>() {
+        hasher.write_u64(kickoff.team as u64);
+        let (stage, left) = match kickoff.stage {
+            KickoffStage::Countdown(left) => (0, left),
+            KickoffStage::Waiting => (1, 0),
+            KickoffStage::Taken => (2, 0),
+        };
+        hasher.write_u64(stage);
+        hasher.write_u64(left);
+    }
+
+    let mut query_balls = world.query_filtered::<(&Transform, &Velocity), With<Ball>>();
+    let balls: Vec<_> = query_balls.iter(world).collect();
+    hasher.write_u64(balls.len() as u64);
+    for (transform, velocity) in balls {
+        write_body(&mut hasher, transform, velocity);
+    }
+
+    let mut query_players = world.query::<(&PlayerId, &Team, &Transform, &Velocity, &KickCharge)>();
+    let mut players: Vec<_> = query_players.iter(world).collect();
+    players.sort_by_key(|(id, ..)| **id);
+    hasher.write_u64(players.len() as u64);
+    for (id, team, transform, velocity, charge) in players {
+        hasher.write_u64(id.0 as u64);
+        hasher.write_u64(*team as u64);
+        write_body(&mut hasher, transform, velocity);
+        hasher.write_u64(charge.ticks as u64);
+        hasher.write_u64(charge.cooldown as u64);
+    }
+
+    hasher.0
+}
+
+fn write_body(hasher: &mut Fnv1a, transform: &Transform, velocity: &Velocity) {
+    hasher.write_f32(transform.translation.x);
+    hasher.write_f32(transform.translation.y);
+    hasher.write_f32(velocity.x);
+    hasher.write_f32(velocity.y);
+}
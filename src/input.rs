@@ -0,0 +1,432 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ai::Difficulty;
+use crate::simulation::Team;
+
+// Key bindings are read from and saved to this file in the working directory.
+pub const CONTROLS_FILE: &str = "controls.ron";
+// Stick positions closer to the center than this are treated as no movement.
+pub const GAMEPAD_DEADZONE: f32 = 0.15;
+
+// What a player wants to do in the current simulation step. Filled by the
+// player's InputSource before any physics runs; the physics only ever reads
+// this component.
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlayerInput {
+    // Desired direction of movement, each axis in [-1, 1].
+    pub movement: Vec2,
+    // Held down. The kick is charged while it is held and taken when it is
+    // released.
+    pub kick: bool,
+    // Held down. Control switches to a teammate when it is first pressed.
+    pub switch: bool,
+}
+
+// Whether switch was held in the previous step by the player controlling
+// this one, so holding it down switches only once.
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SwitchHeld(pub bool);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    Kick,
+    Switch,
+}
+
+impl Action {
+    pub const ALL: [Action; 6] = [
+        Action::Up,
+        Action::Down,
+        Action::Left,
+        Action::Right,
+        Action::Kick,
+        Action::Switch,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBindings {
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub kick: KeyCode,
+    #[serde(default = "default_switch_key")]
+    pub switch: KeyCode,
+}
+
+// Switch key of controls saved before switching players was possible.
+fn default_switch_key() -> KeyCode {
+    KeyCode::Q
+}
+
+impl KeyBindings {
+    pub fn wasd() -> Self {
+        Self {
+            up: KeyCode::W,
+            down: KeyCode::S,
+            left: KeyCode::A,
+            right: KeyCode::D,
+            kick: KeyCode::Space,
+            switch: KeyCode::Q,
+        }
+    }
+
+    pub fn arrows() -> Self {
+        Self {
+            up: KeyCode::Up,
+            down: KeyCode::Down,
+            left: KeyCode::Left,
+            right: KeyCode::Right,
+            kick: KeyCode::RControl,
+            switch: KeyCode::RShift,
+        }
+    }
+
+    pub fn key(&self, action: Action) -> KeyCode {
+        match action {
+            Action::Up => self.up,
+            Action::Down => self.down,
+            Action::Left => self.left,
+            Action::Right => self.right,
+            Action::Kick => self.kick,
+            Action::Switch => self.switch,
+        }
+    }
+
+    pub fn set_key(&mut self, action: Action, key: KeyCode) {
+        match action {
+            Action::Up => self.up = key,
+            Action::Down => self.down = key,
+            Action::Left => self.left = key,
+            Action::Right => self.right = key,
+            Action::Kick => self.kick = key,
+            Action::Switch => self.switch = key,
+        }
+    }
+}
+
+// Key bindings of both players, as stored in CONTROLS_FILE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Controls {
+    pub red: KeyBindings,
+    pub blue: KeyBindings,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Self {
+            red: KeyBindings::wasd(),
+            blue: KeyBindings::arrows(),
+        }
+    }
+}
+
+impl Controls {
+    pub fn get(&self, team: Team) -> &KeyBindings {
+        match team {
+            Team::Red => &self.red,
+            Team::Blue => &self.blue,
+        }
+    }
+
+    pub fn get_mut(&mut self, team: Team) -> &mut KeyBindings {
+        match team {
+            Team::Red => &mut self.red,
+            Team::Blue => &mut self.blue,
+        }
+    }
+
+    // Falls back to the default bindings when the file is missing or broken.
+    pub fn load_or_default(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Self::default(),
+        };
+        match ron::from_str(&text) {
+            Ok(controls) => controls,
+            Err(err) => {
+                warn!("Ignoring invalid controls in {}: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        fs::write(path, ron::ser::to_string_pretty(self, Default::default())?)?;
+        Ok(())
+    }
+}
+
+// Gamepad of each team, if any. The keyboard controlled player of the team
+// can use both the keyboard and the assigned gamepad.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GamepadAssignments {
+    pub red: Option<Gamepad>,
+    pub blue: Option<Gamepad>,
+}
+
+impl GamepadAssignments {
+    pub fn get(&self, team: Team) -> Option<Gamepad> {
+        match team {
+            Team::Red => self.red,
+            Team::Blue => self.blue,
+        }
+    }
+}
+
+// Where the PlayerInput of a player comes from.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub enum InputSource {
+    Keyboard(KeyBindings),
+    // Written from outside of the simulation, e.g. by a bot harness or the
+    // network code, before SimulationLabel::Input runs.
+    External,
+    // Played back by ReplayPlugin.
+    Replay,
+    // Controlled by the computer.
+    Ai(Difficulty),
+    // Nobody controls the player, it just stands still.
+    Idle,
+}
+
+impl InputSource {
+    // Whether a person controls the player, here or elsewhere.
+    pub fn is_human(&self) -> bool {
+        matches!(
+            self,
+            InputSource::Keyboard(_) | InputSource::External | InputSource::Replay
+        )
+    }
+}
+
+// Input sources given to the first player of each team when a match starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputSources {
+    pub red: InputSource,
+    pub blue: InputSource,
+}
+
+impl InputSources {
+    pub fn get(&self, team: Team) -> &InputSource {
+        match team {
+            Team::Red => &self.red,
+            Team::Blue => &self.blue,
+        }
+    }
+
+    pub fn keyboard(controls: &Controls) -> Self {
+        Self {
+            red: InputSource::Keyboard(controls.red),
+            blue: InputSource::Keyboard(controls.blue),
+        }
+    }
+}
+
+impl Default for InputSources {
+    fn default() -> Self {
+        Self::keyboard(&Controls::default())
+    }
+}
+
+// Fills PlayerInput of the keyboard controlled players.
+pub fn keyboard_input_system(
+    kb: Res<Input<KeyCode>>,
+    mut query: Query<(&InputSource, &mut PlayerInput)>,
+) {
+    for (source, mut input) in query.iter_mut() {
+        if let InputSource::Keyboard(bindings) = source {
+            let axis = |positive: KeyCode, negative: KeyCode| {
+                if kb.pressed(positive) {
+                    1.0
+                } else if kb.pressed(negative) {
+                    -1.0
+                } else {
+                    0.0
+                }
+            };
+            *input = PlayerInput {
+                movement: Vec2::new(
+                    axis(bindings.right, bindings.left),
+                    axis(bindings.up, bindings.down),
+                ),
+                kick: kb.pressed(bindings.kick),
+                switch: kb.pressed(bindings.switch),
+            };
+        }
+    }
+}
+
+// Keeps GamepadAssignments in sync with the connected gamepads: unplugged
+// gamepads are unassigned and newly plugged ones are given to the first
+// player without a gamepad.
+pub fn assign_gamepads_system(
+    gamepads: Res<Gamepads>,
+    mut assignments: ResMut<GamepadAssignments>,
+) {
+    if !gamepads.is_changed() {
+        return;
+    }
+    let GamepadAssignments { red, blue } = &mut *assignments;
+
+    for slot in [&mut *red, &mut *blue] {
+        if let Some(gamepad) = slot {
+            if !gamepads.contains(gamepad) {
+                info!("Gamepad {} disconnected", gamepad.0);
+                *slot = None;
+            }
+        }
+    }
+
+    let mut connected: Vec<Gamepad> = gamepads.iter().copied().collect();
+    connected.sort_by_key(|gamepad| gamepad.0);
+    for gamepad in connected {
+        if *red == Some(gamepad) || *blue == Some(gamepad) {
+            continue;
+        }
+        if let Some(slot) = [&mut *red, &mut *blue]
+            .into_iter()
+            .find(|slot| slot.is_none())
+        {
+            info!("Gamepad {} connected", gamepad.0);
+            *slot = Some(gamepad);
+        }
+    }
+}
+
+// Adds the input of the assigned gamepads to the keyboard controlled players.
+// The left stick moves (its tilt scales the speed), the south
+// button kicks and the west button switches players.
+pub fn gamepad_input_system(
+    assignments: Res<GamepadAssignments>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<Input<GamepadButton>>,
+    mut query: Query<(&InputSource, &mut PlayerInput, &Team)>,
+) {
+    for (source, mut input, team) in query.iter_mut() {
+        let gamepad = match (source, assignments.get(*team)) {
+            (InputSource::Keyboard(_), Some(gamepad)) => gamepad,
+            _ => continue,
+        };
+
+        let axis = |axis_type| {
+            axes.get(GamepadAxis(gamepad, axis_type))
+                .unwrap_or_default()
+        };
+        let stick = Vec2::new(
+            axis(GamepadAxisType::LeftStickX),
+            axis(GamepadAxisType::LeftStickY),
+        );
+        if stick.length() > GAMEPAD_DEADZONE {
+            input.movement = stick.clamp_length_max(1.0);
+        }
+        input.kick |= buttons.pressed(GamepadButton(gamepad, GamepadButtonType::South));
+        input.switch |= buttons.pressed(GamepadButton(gamepad, GamepadButtonType::West));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::event::Events;
+    use bevy::input::gamepad::{gamepad_connection_system, GamepadEvent, GamepadEventType};
+
+    use super::*;
+
+    fn gamepad_app() -> App {
+        let mut app = App::new();
+        app.add_event::<GamepadEvent>()
+            .init_resource::<Gamepads>()
+            .init_resource::<GamepadAssignments>()
+            .add_system_to_stage(CoreStage::PreUpdate, gamepad_connection_system)
+            .add_system(assign_gamepads_system);
+        app
+    }
+
+    fn send(app: &mut App, gamepad: usize, event_type: GamepadEventType) {
+        app.world
+            .resource_mut::<Events<GamepadEvent>>()
+            .send(GamepadEvent(Gamepad(gamepad), event_type));
+        app.update();
+    }
+
+    #[test]
+    fn gamepads_are_assigned_when_plugged_in_and_out() {
+        let mut app = gamepad_app();
+        send(&mut app, 0, GamepadEventType::Connected);
+        send(&mut app, 1, GamepadEventType::Connected);
+        assert_eq!(
+            *app.world.resource::<GamepadAssignments>(),
+            GamepadAssignments {
+                red: Some(Gamepad(0)),
+                blue: Some(Gamepad(1)),
+            }
+        );
+
+        // Red loses its gamepad, and gets the next one plugged in.
+        send(&mut app, 0, GamepadEventType::Disconnected);
+        assert_eq!(app.world.resource::<GamepadAssignments>().red, None);
+        assert_eq!(
+            app.world.resource::<GamepadAssignments>().blue,
+            Some(Gamepad(1))
+        );
+        send(&mut app, 2, GamepadEventType::Connected);
+        assert_eq!(
+            app.world.resource::<GamepadAssignments>().red,
+            Some(Gamepad(2))
+        );
+    }
+
+    // Runs gamepad_input_system for a red keyboard player moving right on the
+    // keyboard, with the left stick of its gamepad tilted as given.
+    fn movement_with_stick(stick: Vec2) -> Vec2 {
+        let mut world = World::new();
+        let gamepad = Gamepad(0);
+        let mut axes = Axis::<GamepadAxis>::default();
+        axes.set(GamepadAxis(gamepad, GamepadAxisType::LeftStickX), stick.x);
+        axes.set(GamepadAxis(gamepad, GamepadAxisType::LeftStickY), stick.y);
+        world.insert_resource(axes);
+        world.insert_resource(Input::<GamepadButton>::default());
+        world.insert_resource(GamepadAssignments {
+            red: Some(gamepad),
+            blue: None,
+        });
+        let player = world
+            .spawn()
+            .insert(InputSource::Keyboard(KeyBindings::wasd()))
+            .insert(Team::Red)
+            .insert(PlayerInput {
+                movement: Vec2::X,
+                ..Default::default()
+            })
+            .id();
+
+        let mut stage = SystemStage::single(gamepad_input_system);
+        stage.run(&mut world);
+        world
+            .get::<PlayerInput>(player)
+            .expect("the player has no PlayerInput")
+            .movement
+    }
+
+    #[test]
+    fn half_tilt_gives_half_movement() {
+        let movement = movement_with_stick(Vec2::new(0.0, 0.5));
+        assert_eq!(movement, Vec2::new(0.0, 0.5));
+    }
+
+    #[test]
+    fn tilt_in_deadzone_keeps_keyboard_input() {
+        let movement = movement_with_stick(Vec2::new(0.0, GAMEPAD_DEADZONE / 2.0));
+        assert_eq!(movement, Vec2::X);
+    }
+}
@@ -0,0 +1,39 @@
+use bevy::ecs::schedule::StateError;
+use bevy::prelude::*;
+
+pub mod ai;
+pub mod collision;
+pub mod determinism;
+pub mod input;
+pub mod menu;
+pub mod pitch;
+pub mod render;
+pub mod replay;
+pub mod rules;
+pub mod simulation;
+
+pub use pitch::PitchPlugin;
+pub use render::RenderPlugin;
+pub use replay::ReplayPlugin;
+pub use simulation::SimulationPlugin;
+
+// Constants
+pub const WINDOW_WIDTH: f32 = 1024.0;
+pub const WINDOW_HEIGHT: f32 = 768.0;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
+pub enum GameState {
+    InMenu,
+    InGame,
+    GameOver,
+    Paused,
+}
+
+// Several changes of the state can be requested in the same frame, e.g. by
+// pressing Escape while clicking Resume. The first one is made and the
+// others are skipped.
+pub(crate) fn change_state(result: Result<(), StateError>) {
+    if let Err(err) = result {
+        debug!("Skipped a change of the game state: {:?}", err);
+    }
+}
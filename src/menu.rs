@@ -1,83 +1,351 @@
-use crate::{GameState, FONT};
-use crate::{PITCH1_SPRITE, PITCH2_SPRITE, PITCH3_SPRITE};
+use crate::ai::Difficulty;
+use crate::input::{Action, Controls, InputSource, InputSources, CONTROLS_FILE};
+use crate::pitch::{PitchDefinition, PitchHandles, SelectedPitch};
+use crate::render::FONT;
+use crate::rules::{KickControl, MatchEnded, MatchRules};
+use crate::simulation::{MatchConfig, Team, MAX_TEAM_SIZE};
+use crate::{change_state, GameState};
 use bevy::app::AppExit;
 use bevy::{prelude::*, ui::FocusPolicy};
 
 pub struct Menu;
 
+#[derive(Component)]
+struct MenuRoot;
+
 #[derive(Component)]
 enum MenuItem {
     Start,
+    ChangePlayers,
     ChangePitch,
+    ChangeTeamSize,
+    ChangeRules,
+    ChangeKicks,
+    Controls,
     Quit,
+    Back,
+    Rematch,
+    MainMenu,
+    Resume,
+    Restart,
+    QuitToMenu,
 }
 
-#[derive(Component, Clone)]
-pub enum Background {
-    Pitch1,
-    Pitch2,
-    Pitch3,
-}
+// Button on the controls page, rebinding one action of one player.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+struct BindingButton(Team, Action);
+
+// Everything that is replaced when switching between menu pages.
+type PageButtons = Or<(With<MenuItem>, With<BindingButton>)>;
+
+// The binding button that was clicked and waits for a key press.
+#[derive(Default)]
+struct WaitingForKey(Option<BindingButton>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+struct GameOverLabel;
+
+// Preview of the selected pitch behind the menu.
+#[derive(Component)]
+struct Background;
 
 impl Plugin for Menu {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(init_menu_system)
+        app.init_resource::<Controls>()
+            .init_resource::<WaitingForKey>()
+            .add_startup_system(spawn_ui_camera_system)
+            .add_system_set(SystemSet::on_enter(GameState::InMenu).with_system(init_menu_system))
             .add_system(handle_buttons)
-            .add_system_set(SystemSet::on_exit(GameState::InMenu).with_system(despawn_menu));
+            .add_system(handle_binding_buttons)
+            .add_system(rebind_system)
+            .add_system(binding_labels_system)
+            .add_system(match_config_labels_system)
+            .add_system(pitch_preview_system)
+            .add_system_set(SystemSet::on_exit(GameState::InMenu).with_system(despawn_menu))
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(game_over_system.label(GameOverLabel)),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::GameOver).with_system(init_game_over_system),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(despawn_menu))
+            // The game over screen wins over pausing in the same frame, or
+            // the end of the match would be missed.
+            .add_system(pause_system.after(GameOverLabel))
+            .add_system_set(SystemSet::on_enter(GameState::Paused).with_system(init_pause_system))
+            .add_system_set(SystemSet::on_exit(GameState::Paused).with_system(despawn_menu));
     }
 }
 
-// Removes all components of a menu (except Background).
-fn despawn_menu(
-    mut commands: Commands,
-    query: Query<Entity>,
-    query_background: Query<&Background>,
-) {
-    let background = query_background.iter().next().unwrap();
+// Removes all components of a menu.
+fn despawn_menu(mut commands: Commands, query: Query<Entity, With<MenuRoot>>) {
     for ent in query.iter() {
         commands.entity(ent).despawn_recursive();
     }
-    commands.spawn().insert((*background).clone());
 }
 
 // Do the action given by the pressed button.
+#[allow(clippy::too_many_arguments)]
 fn handle_buttons(
+    mut commands: Commands,
     mut app_exit_events: EventWriter<AppExit>,
     mut app_state: ResMut<State<GameState>>,
     query: Query<(&Interaction, &MenuItem), Changed<Interaction>>,
-    mut query_background: Query<(&mut Background, &mut UiImage)>,
+    query_root: Query<Entity, With<MenuRoot>>,
+    query_buttons: Query<Entity, PageButtons>,
     asset_server: Res<AssetServer>,
+    (controls, mut input_sources): (Res<Controls>, ResMut<InputSources>),
+    mut waiting: ResMut<WaitingForKey>,
+    mut config: ResMut<MatchConfig>,
+    (pitch_handles, pitches, mut selected_pitch): (
+        Res<PitchHandles>,
+        Res<Assets<PitchDefinition>>,
+        ResMut<SelectedPitch>,
+    ),
 ) {
     // If button clicked, change state
     for (interaction, item) in query.iter() {
         if interaction == &Interaction::Clicked {
             match item {
                 MenuItem::Start => {
-                    app_state
-                        .set(GameState::InGame)
-                        .expect("Something went wrong!");
+                    let pitch = selected_pitch
+                        .0
+                        .as_ref()
+                        .and_then(|handle| pitches.get(handle));
+                    if let Some(pitch) = pitch {
+                        pitch.apply(&mut config);
+                    }
+                    change_state(app_state.set(GameState::InGame));
                 }
-                MenuItem::ChangePitch => {
-                    let (mut pitch_type, mut pitch_image) =
-                        query_background.iter_mut().next().unwrap();
-                    match *pitch_type {
-                        Background::Pitch1 => {
-                            *pitch_type = Background::Pitch2;
-                            *pitch_image = asset_server.load(PITCH2_SPRITE).into();
-                        }
-                        Background::Pitch2 => {
-                            *pitch_type = Background::Pitch3;
-                            *pitch_image = asset_server.load(PITCH3_SPRITE).into();
-                        }
-                        Background::Pitch3 => {
-                            *pitch_type = Background::Pitch1;
-                            *pitch_image = asset_server.load(PITCH1_SPRITE).into();
+                MenuItem::ChangePlayers => {
+                    // Two players, then the AI in blue at every difficulty.
+                    input_sources.blue = match input_sources.blue {
+                        InputSource::Keyboard(_) => InputSource::Ai(Difficulty::ALL[0]),
+                        InputSource::Ai(difficulty) => {
+                            match Difficulty::ALL.iter().position(|d| *d == difficulty) {
+                                Some(index) if index + 1 < Difficulty::ALL.len() => {
+                                    InputSource::Ai(Difficulty::ALL[index + 1])
+                                }
+                                _ => InputSource::Keyboard(controls.blue),
+                            }
                         }
+                        _ => continue,
+                    };
+                }
+                MenuItem::ChangePitch => {
+                    // Select the pitch after the selected one, or the first
+                    // one after the last.
+                    let loaded = pitch_handles.loaded(&pitches);
+                    let next = loaded
+                        .iter()
+                        .position(|(handle, _)| Some(handle) == selected_pitch.0.as_ref())
+                        .map_or(0, |index| (index + 1) % loaded.len());
+                    if let Some((handle, _)) = loaded.get(next) {
+                        selected_pitch.0 = Some(handle.clone());
                     }
                 }
+                MenuItem::ChangeTeamSize => {
+                    config.team_size = config.team_size % MAX_TEAM_SIZE + 1;
+                }
+                MenuItem::ChangeRules => {
+                    // The kick control stays as chosen.
+                    let kick_control = config.rules.kick_control;
+                    let presets = MatchRules::PRESETS.map(|rules| MatchRules {
+                        kick_control,
+                        ..rules
+                    });
+                    let next = presets
+                        .iter()
+                        .position(|rules| *rules == config.rules)
+                        .map_or(0, |index| (index + 1) % presets.len());
+                    config.rules = presets[next];
+                }
+                MenuItem::ChangeKicks => {
+                    let all = KickControl::ALL;
+                    let next = all
+                        .iter()
+                        .position(|kick_control| *kick_control == config.rules.kick_control)
+                        .map_or(0, |index| (index + 1) % all.len());
+                    config.rules.kick_control = all[next];
+                }
+                MenuItem::Controls => {
+                    switch_page(&mut commands, &query_root, &query_buttons, |parent| {
+                        spawn_controls_page(parent, &asset_server, &controls)
+                    });
+                }
                 MenuItem::Quit => {
                     app_exit_events.send(AppExit);
                 }
+                MenuItem::Back => {
+                    waiting.0 = None;
+                    switch_page(&mut commands, &query_root, &query_buttons, |parent| {
+                        spawn_main_page(parent, &asset_server)
+                    });
+                }
+                // The match is below the game over screen or the pause menu on
+                // the state stack, replacing the whole stack leaves it.
+                MenuItem::Rematch | MenuItem::Restart => {
+                    change_state(app_state.replace(GameState::InGame));
+                }
+                MenuItem::MainMenu | MenuItem::QuitToMenu => {
+                    change_state(app_state.replace(GameState::InMenu));
+                }
+                MenuItem::Resume => {
+                    change_state(app_state.pop());
+                }
+            }
+        }
+    }
+}
+
+// Replaces all buttons of the menu with the ones spawned by spawn_page.
+fn switch_page(
+    commands: &mut Commands,
+    query_root: &Query<Entity, With<MenuRoot>>,
+    query_buttons: &Query<Entity, PageButtons>,
+    spawn_page: impl FnOnce(&mut ChildBuilder),
+) {
+    for button in query_buttons.iter() {
+        commands.entity(button).despawn_recursive();
+    }
+    let root = query_root.iter().next().unwrap();
+    commands.entity(root).with_children(spawn_page);
+}
+
+// Clicking a binding button makes it wait for the new key.
+fn handle_binding_buttons(
+    query: Query<(&Interaction, &BindingButton), Changed<Interaction>>,
+    mut waiting: ResMut<WaitingForKey>,
+) {
+    for (interaction, button) in query.iter() {
+        if interaction == &Interaction::Clicked {
+            waiting.0 = Some(*button);
+        }
+    }
+}
+
+// Binds the next pressed key to the action waiting for it (Escape cancels)
+// and saves the controls.
+fn rebind_system(
+    kb: Res<Input<KeyCode>>,
+    mut waiting: ResMut<WaitingForKey>,
+    mut controls: ResMut<Controls>,
+    mut input_sources: ResMut<InputSources>,
+) {
+    let BindingButton(team, action) = match waiting.0 {
+        Some(button) => button,
+        None => return,
+    };
+    let key = match kb.get_just_pressed().next() {
+        Some(key) => *key,
+        None => return,
+    };
+    waiting.0 = None;
+    if key == KeyCode::Escape {
+        return;
+    }
+
+    controls.get_mut(team).set_key(action, key);
+    if let InputSource::Keyboard(bindings) = &mut input_sources.red {
+        *bindings = controls.red;
+    }
+    if let InputSource::Keyboard(bindings) = &mut input_sources.blue {
+        *bindings = controls.blue;
+    }
+    if let Err(err) = controls.save(CONTROLS_FILE) {
+        error!("Failed to save controls to {}: {}", CONTROLS_FILE, err);
+    }
+}
+
+fn binding_label(button: BindingButton, controls: &Controls, waiting: &WaitingForKey) -> String {
+    let BindingButton(team, action) = button;
+    let key = if waiting.0 == Some(button) {
+        "...".to_string()
+    } else {
+        format!("{:?}", controls.get(team).key(action))
+    };
+    format!("{:?} {:?}: {}", team, action, key)
+}
+
+// Shows the current key of every binding button.
+fn binding_labels_system(
+    controls: Res<Controls>,
+    waiting: Res<WaitingForKey>,
+    query: Query<(&BindingButton, &Children)>,
+    mut query_text: Query<&mut Text>,
+) {
+    if !controls.is_changed() && !waiting.is_changed() {
+        return;
+    }
+    for (button, children) in query.iter() {
+        for child in children.iter() {
+            if let Ok(mut text) = query_text.get_mut(*child) {
+                text.sections[0].value = binding_label(*button, &controls, &waiting);
+            }
+        }
+    }
+}
+
+// Shows the selected players, team size, rules and kicks on their buttons, e.g.
+// "2 vs 2".
+fn match_config_labels_system(
+    config: Res<MatchConfig>,
+    input_sources: Res<InputSources>,
+    query: Query<(&MenuItem, &Children)>,
+    mut query_text: Query<&mut Text>,
+) {
+    for (item, children) in query.iter() {
+        let label = match item {
+            MenuItem::ChangePlayers => match input_sources.blue {
+                InputSource::Ai(difficulty) => format!("1 Player: {:?}", difficulty),
+                _ => "2 Players".to_string(),
+            },
+            MenuItem::ChangeTeamSize => format!("{0} vs {0}", config.team_size),
+            MenuItem::ChangeRules => config.rules.describe(),
+            MenuItem::ChangeKicks => format!("{:?} Kicks", config.rules.kick_control),
+            _ => continue,
+        };
+        for child in children.iter() {
+            if let Ok(mut text) = query_text.get_mut(*child) {
+                if text.sections[0].value != label {
+                    text.sections[0].value = label.clone();
+                }
+            }
+        }
+    }
+}
+
+// Shows the selected pitch behind the menu and its name on the pitch button.
+fn pitch_preview_system(
+    asset_server: Res<AssetServer>,
+    selected: Res<SelectedPitch>,
+    pitches: Res<Assets<PitchDefinition>>,
+    mut query_background: Query<(&mut UiImage, &mut Visibility), With<Background>>,
+    query: Query<(&MenuItem, &Children)>,
+    mut query_text: Query<&mut Text>,
+) {
+    let pitch = match selected.0.as_ref().and_then(|handle| pitches.get(handle)) {
+        Some(pitch) => pitch,
+        None => return,
+    };
+    for (mut image, mut visibility) in query_background.iter_mut() {
+        // The background is hidden again whenever the menu is spawned.
+        if selected.is_changed() || !visibility.is_visible {
+            *image = asset_server.load(pitch.texture.as_str()).into();
+            visibility.is_visible = true;
+        }
+    }
+
+    let label = format!("Pitch: {}", pitch.name);
+    for (item, children) in query.iter() {
+        if let MenuItem::ChangePitch = item {
+            for child in children.iter() {
+                if let Ok(mut text) = query_text.get_mut(*child) {
+                    if text.sections[0].value != label {
+                        text.sections[0].value = label.clone();
+                    }
+                }
             }
         }
     }
@@ -103,8 +371,19 @@ fn spawn_button(parent: &mut ChildBuilder, asset_server: &Res<AssetServer>, item
                 text: Text::with_section(
                     match item {
                         MenuItem::Start => "Start",
+                        MenuItem::ChangePlayers => "Players",
                         MenuItem::ChangePitch => "Change Pitch",
+                        MenuItem::ChangeTeamSize => "Team Size",
+                        MenuItem::ChangeRules => "Rules",
+                        MenuItem::ChangeKicks => "Kicks",
+                        MenuItem::Controls => "Controls",
                         MenuItem::Quit => "Quit",
+                        MenuItem::Back => "Back",
+                        MenuItem::Rematch => "Rematch",
+                        MenuItem::MainMenu => "Main Menu",
+                        MenuItem::Resume => "Resume",
+                        MenuItem::Restart => "Restart",
+                        MenuItem::QuitToMenu => "Quit to Menu",
                     },
                     TextStyle {
                         font: asset_server.load(FONT),
@@ -120,24 +399,101 @@ fn spawn_button(parent: &mut ChildBuilder, asset_server: &Res<AssetServer>, item
         .insert(item);
 }
 
+// Spawns a button rebinding one action, as the child of the given parent.
+fn spawn_binding_button(
+    parent: &mut ChildBuilder,
+    asset_server: &Res<AssetServer>,
+    controls: &Controls,
+    button: BindingButton,
+) {
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                align_self: AlignSelf::Center,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                size: Size::new(Val::Percent(30.0), Val::Percent(6.0)),
+                margin: Rect::all(Val::Auto),
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    binding_label(button, controls, &WaitingForKey(None)),
+                    TextStyle {
+                        font: asset_server.load(FONT),
+                        font_size: 28.0,
+                        color: Color::rgb(0.9, 0.9, 0.9),
+                    },
+                    Default::default(),
+                ),
+                focus_policy: FocusPolicy::Pass,
+                ..Default::default()
+            });
+        })
+        .insert(button);
+}
+
+fn spawn_main_page(parent: &mut ChildBuilder, asset_server: &Res<AssetServer>) {
+    spawn_button(parent, asset_server, MenuItem::Start);
+    spawn_button(parent, asset_server, MenuItem::ChangePlayers);
+    spawn_button(parent, asset_server, MenuItem::ChangePitch);
+    spawn_button(parent, asset_server, MenuItem::ChangeTeamSize);
+    spawn_button(parent, asset_server, MenuItem::ChangeRules);
+    spawn_button(parent, asset_server, MenuItem::ChangeKicks);
+    spawn_button(parent, asset_server, MenuItem::Controls);
+    spawn_button(parent, asset_server, MenuItem::Quit);
+}
+
+fn spawn_controls_page(
+    parent: &mut ChildBuilder,
+    asset_server: &Res<AssetServer>,
+    controls: &Controls,
+) {
+    for team in Team::ALL {
+        for action in Action::ALL {
+            spawn_binding_button(parent, asset_server, controls, BindingButton(team, action));
+        }
+    }
+    spawn_button(parent, asset_server, MenuItem::Back);
+}
+
 // Spawns background image as the child of the given parent.
-fn spawn_background(parent: &mut ChildBuilder, asset_server: &Res<AssetServer>) {
+// It stays hidden until a pitch is selected.
+fn spawn_background(parent: &mut ChildBuilder) {
     parent
         .spawn_bundle(ImageBundle {
             style: Style {
                 size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
                 ..Default::default()
             },
-            image: asset_server.load(PITCH1_SPRITE).into(),
+            visibility: Visibility { is_visible: false },
             ..Default::default()
         })
-        .insert(Background::Pitch1);
+        .insert(Background);
 }
 
-// Creates simple menu.
-fn init_menu_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+// The UI camera stays for the whole game, the menus come and go.
+fn spawn_ui_camera_system(mut commands: Commands) {
     commands.spawn_bundle(UiCameraBundle::default());
+}
+
+// Shows the game over screen over the match once it has ended.
+fn game_over_system(
+    mut match_ended_events: EventReader<MatchEnded>,
+    mut app_state: ResMut<State<GameState>>,
+) {
+    if match_ended_events.iter().next().is_some() {
+        change_state(app_state.push(GameState::GameOver));
+    }
+}
 
+// Spawns a menu covering the whole window, with the children spawned by
+// spawn_page.
+fn spawn_menu(commands: &mut Commands, spawn_page: impl FnOnce(&mut ChildBuilder)) {
     commands
         .spawn_bundle(NodeBundle {
             node: Default::default(),
@@ -148,16 +504,49 @@ fn init_menu_system(mut commands: Commands, asset_server: Res<AssetServer>) {
                 ..Default::default()
             },
             color: Color::NONE.into(),
-            image: UiImage(asset_server.load(PITCH1_SPRITE)),
+            image: Default::default(),
             focus_policy: Default::default(),
             transform: Default::default(),
             global_transform: Default::default(),
             visibility: Visibility { is_visible: true },
         })
-        .with_children(|parent| {
-            spawn_background(parent, &asset_server);
-            spawn_button(parent, &asset_server, MenuItem::Start);
-            spawn_button(parent, &asset_server, MenuItem::ChangePitch);
-            spawn_button(parent, &asset_server, MenuItem::Quit);
-        });
+        .insert(MenuRoot)
+        .with_children(spawn_page);
+}
+
+// Creates simple menu.
+fn init_menu_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_menu(&mut commands, |parent| {
+        spawn_background(parent);
+        spawn_main_page(parent, &asset_server);
+    });
+}
+
+// Escape pauses the match and resumes it again.
+fn pause_system(kb: Res<Input<KeyCode>>, mut app_state: ResMut<State<GameState>>) {
+    if !kb.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    match app_state.current() {
+        GameState::InGame => change_state(app_state.push(GameState::Paused)),
+        GameState::Paused => change_state(app_state.pop()),
+        _ => {}
+    }
+}
+
+// Shows the buttons of the game over screen, over the finished match.
+fn init_game_over_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_menu(&mut commands, |parent| {
+        spawn_button(parent, &asset_server, MenuItem::Rematch);
+        spawn_button(parent, &asset_server, MenuItem::MainMenu);
+    });
+}
+
+// Shows the pause menu over the paused match.
+fn init_pause_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_menu(&mut commands, |parent| {
+        spawn_button(parent, &asset_server, MenuItem::Resume);
+        spawn_button(parent, &asset_server, MenuItem::Restart);
+        spawn_button(parent, &asset_server, MenuItem::QuitToMenu);
+    });
 }
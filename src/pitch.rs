@@ -0,0 +1,341 @@
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::{MatchConfig, PhysicsConfig, Team};
+use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
+
+// Width of the goal mouth of the classic pitch.
+pub const CLASSIC_GOAL_WIDTH: f32 = 200.0;
+pub const CLASSIC_POST_RADIUS: f32 = 10.0;
+pub const CLASSIC_CENTRE_CIRCLE_RADIUS: f32 = 100.0;
+// How far the goal area reaches into the pitch from the goal line.
+pub const GOAL_AREA_DEPTH: f32 = 120.0;
+// Every *.pitch.ron file in this folder (inside assets) is a pitch that can
+// be chosen in the menu.
+pub const PITCHES_FOLDER: &str = "pitches";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub a: Vec2,
+    pub b: Vec2,
+}
+
+impl Segment {
+    pub fn new(a: Vec2, b: Vec2) -> Self {
+        Self { a, b }
+    }
+
+    pub fn closest_point(&self, point: Vec2) -> Vec2 {
+        let edge = self.b - self.a;
+        let u = ((point - self.a).dot(edge) / edge.length_squared()).clamp(0.0, 1.0);
+        self.a + edge * u
+    }
+}
+
+// The mouth of a goal. The ball scores against the defending team once its
+// centre is past the line, on the side away from the centre of the pitch.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Goal {
+    pub defending: Team,
+    pub line: Segment,
+}
+
+impl Goal {
+    pub fn is_scored(&self, ball: Vec2) -> bool {
+        let Segment { a, b } = self.line;
+        let edge = b - a;
+        let u = (ball - a).dot(edge) / edge.length_squared();
+        let mut outward = edge.perp();
+        if outward.dot(-a) > 0.0 {
+            outward = -outward;
+        }
+        (0.0..=1.0).contains(&u) && (ball - a).dot(outward) >= 0.0
+    }
+
+    pub fn centre(&self) -> Vec2 {
+        (self.line.a + self.line.b) / 2.0
+    }
+
+    // Unit vector perpendicular to the goal line, pointing into the pitch.
+    pub fn inward(&self) -> Vec2 {
+        let Segment { a, b } = self.line;
+        let normal = (b - a).perp().normalize_or_zero();
+        if normal.dot(-a) < 0.0 {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    // Position of a point relative to the goal area: along the goal line from
+    // its middle, and into the pitch from the line.
+    fn area_coordinates(&self, point: Vec2) -> (f32, f32) {
+        let along = (self.line.b - self.line.a).normalize_or_zero();
+        let offset = point - self.centre();
+        (offset.dot(along), offset.dot(self.inward()))
+    }
+
+    // The goal area is the box between the ends of the goal line, reaching
+    // GOAL_AREA_DEPTH into the pitch.
+    pub fn in_area(&self, point: Vec2) -> bool {
+        let half_width = self.line.a.distance(self.line.b) / 2.0;
+        let (along, depth) = self.area_coordinates(point);
+        along.abs() <= half_width && (0.0..=GOAL_AREA_DEPTH).contains(&depth)
+    }
+
+    // The point of the goal area closest to the given one, at least margin
+    // away from the edges of the area.
+    pub fn clamp_to_area(&self, point: Vec2, margin: f32) -> Vec2 {
+        let half_width = (self.line.a.distance(self.line.b) / 2.0 - margin).max(0.0);
+        let (along, depth) = self.area_coordinates(point);
+        let along = along.clamp(-half_width, half_width);
+        let depth = depth.clamp(margin, (GOAL_AREA_DEPTH - margin).max(margin));
+        self.centre()
+            + (self.line.b - self.line.a).normalize_or_zero() * along
+            + self.inward() * depth
+    }
+}
+
+// Shape of the pitch, centred at the origin. Walls stop every body. Goal
+// lines only stop the players, so the space behind them is a ball-only zone.
+// Posts are circles, usually at the ends of the goal lines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PitchGeometry {
+    pub walls: Vec<Segment>,
+    pub goals: Vec<Goal>,
+    pub posts: Vec<Vec2>,
+    pub post_radius: f32,
+    // At a kickoff, the team not kicking off stays outside this circle.
+    pub centre_circle_radius: f32,
+}
+
+impl PitchGeometry {
+    // A rectangular pitch with a goal in the middle of the left (red) and
+    // right (blue) edge.
+    pub fn rectangle(width: f32, height: f32, goal_width: f32, post_radius: f32) -> Self {
+        let (x, y, g) = (width / 2., height / 2., goal_width / 2.);
+        let mut geometry = Self {
+            walls: vec![
+                Segment::new(Vec2::new(-x, y), Vec2::new(x, y)),
+                Segment::new(Vec2::new(-x, -y), Vec2::new(x, -y)),
+            ],
+            goals: Vec::new(),
+            posts: Vec::new(),
+            post_radius,
+            centre_circle_radius: CLASSIC_CENTRE_CIRCLE_RADIUS,
+        };
+        for team in Team::ALL {
+            let x = team.side() * x;
+            geometry
+                .walls
+                .push(Segment::new(Vec2::new(x, y), Vec2::new(x, g)));
+            geometry
+                .walls
+                .push(Segment::new(Vec2::new(x, -g), Vec2::new(x, -y)));
+            geometry.goals.push(Goal {
+                defending: team,
+                line: Segment::new(Vec2::new(x, g), Vec2::new(x, -g)),
+            });
+            geometry.posts.push(Vec2::new(x, g));
+            geometry.posts.push(Vec2::new(x, -g));
+        }
+        geometry
+    }
+
+    // Each team defends the other goal from now on.
+    pub fn switch_sides(&mut self) {
+        for goal in self.goals.iter_mut() {
+            goal.defending = goal.defending.opponent();
+        }
+    }
+
+    // Segments a body collides with.
+    pub fn barriers(&self, ball: bool) -> impl Iterator<Item = &Segment> {
+        let goal_lines = self.goals.iter().map(|goal| &goal.line);
+        self.walls.iter().chain(goal_lines.filter(move |_| !ball))
+    }
+}
+
+impl Default for PitchGeometry {
+    // The pitch drawn by the pitch sprites, filling the whole window.
+    fn default() -> Self {
+        Self::rectangle(
+            WINDOW_WIDTH,
+            WINDOW_HEIGHT,
+            CLASSIC_GOAL_WIDTH,
+            CLASSIC_POST_RADIUS,
+        )
+    }
+}
+
+// A pitch as described in a *.pitch.ron file.
+#[derive(Debug, Clone, PartialEq, Deserialize, TypeUuid)]
+#[uuid = "d11679ce-de50-41d3-8090-2a766e4372a2"]
+pub struct PitchDefinition {
+    // Shown in the menu.
+    pub name: String,
+    // Path of the image of the pitch, relative to the assets folder.
+    pub texture: String,
+    pub width: f32,
+    pub height: f32,
+    pub goal_width: f32,
+    #[serde(default = "default_post_radius")]
+    pub post_radius: f32,
+    #[serde(default = "default_centre_circle_radius")]
+    pub centre_circle_radius: f32,
+    // How much the ball slows down in every step.
+    pub ball_friction: f32,
+    // How bouncy the walls are, from 0 to 1.
+    #[serde(default = "default_wall_restitution")]
+    pub wall_restitution: f32,
+    // Mass and bounciness of the players and the ball. Without them the
+    // pitch plays like the default PhysicsConfig.
+    #[serde(default = "default_player_mass")]
+    pub player_mass: f32,
+    #[serde(default = "default_player_restitution")]
+    pub player_restitution: f32,
+    #[serde(default = "default_ball_mass")]
+    pub ball_mass: f32,
+    #[serde(default = "default_ball_restitution")]
+    pub ball_restitution: f32,
+    // Radius of human controlled goalkeepers inside their goal area, if they
+    // are bigger there.
+    #[serde(default)]
+    pub goalkeeper_radius: Option<f32>,
+    // Walls in addition to the edges of the pitch.
+    #[serde(default)]
+    pub walls: Vec<Segment>,
+}
+
+fn default_post_radius() -> f32 {
+    CLASSIC_POST_RADIUS
+}
+
+fn default_centre_circle_radius() -> f32 {
+    CLASSIC_CENTRE_CIRCLE_RADIUS
+}
+
+fn default_wall_restitution() -> f32 {
+    1.0
+}
+
+fn default_player_mass() -> f32 {
+    PhysicsConfig::default().player_mass
+}
+
+fn default_player_restitution() -> f32 {
+    PhysicsConfig::default().player_restitution
+}
+
+fn default_ball_mass() -> f32 {
+    PhysicsConfig::default().ball_mass
+}
+
+fn default_ball_restitution() -> f32 {
+    PhysicsConfig::default().ball_restitution
+}
+
+impl PitchDefinition {
+    pub fn geometry(&self) -> PitchGeometry {
+        let mut geometry =
+            PitchGeometry::rectangle(self.width, self.height, self.goal_width, self.post_radius);
+        geometry.centre_circle_radius = self.centre_circle_radius;
+        geometry.walls.extend(self.walls.iter().copied());
+        geometry
+    }
+
+    // Makes the next match be played on this pitch.
+    pub fn apply(&self, config: &mut MatchConfig) {
+        config.pitch = self.geometry();
+        config.pitch_texture = Some(self.texture.clone());
+        config.physics.ball_friction = self.ball_friction;
+        config.physics.wall_restitution = self.wall_restitution;
+        config.physics.player_mass = self.player_mass;
+        config.physics.player_restitution = self.player_restitution;
+        config.physics.ball_mass = self.ball_mass;
+        config.physics.ball_restitution = self.ball_restitution;
+        config.physics.goalkeeper_radius = self.goalkeeper_radius;
+    }
+}
+
+#[derive(Default)]
+pub struct PitchLoader;
+
+impl AssetLoader for PitchLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let definition: PitchDefinition = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(definition));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["pitch.ron"]
+    }
+}
+
+// Handles of all pitches in PITCHES_FOLDER, keeping them loaded.
+#[derive(Default)]
+pub struct PitchHandles(pub Vec<Handle<PitchDefinition>>);
+
+impl PitchHandles {
+    // The pitches that finished loading, ordered by name.
+    pub fn loaded<'a>(
+        &self,
+        pitches: &'a Assets<PitchDefinition>,
+    ) -> Vec<(Handle<PitchDefinition>, &'a PitchDefinition)> {
+        let mut loaded: Vec<_> = self
+            .0
+            .iter()
+            .filter_map(|handle| Some((handle.clone(), pitches.get(handle)?)))
+            .collect();
+        loaded.sort_by(|(_, pitch1), (_, pitch2)| pitch1.name.cmp(&pitch2.name));
+        loaded
+    }
+}
+
+// The pitch chosen in the menu.
+#[derive(Default)]
+pub struct SelectedPitch(pub Option<Handle<PitchDefinition>>);
+
+// Loads the pitch files and selects the first pitch. Requires the AssetPlugin.
+pub struct PitchPlugin;
+
+impl Plugin for PitchPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<PitchDefinition>()
+            .init_asset_loader::<PitchLoader>()
+            .init_resource::<PitchHandles>()
+            .init_resource::<SelectedPitch>()
+            .add_startup_system(load_pitches_system)
+            .add_system(select_default_pitch_system);
+    }
+}
+
+fn load_pitches_system(asset_server: Res<AssetServer>, mut handles: ResMut<PitchHandles>) {
+    match asset_server.load_folder(PITCHES_FOLDER) {
+        Ok(folder) => handles.0 = folder.into_iter().map(|handle| handle.typed()).collect(),
+        Err(err) => error!("Failed to load pitches from {}: {}", PITCHES_FOLDER, err),
+    }
+}
+
+// Selects the first pitch once it is loaded, unless one is selected already.
+fn select_default_pitch_system(
+    handles: Res<PitchHandles>,
+    pitches: Res<Assets<PitchDefinition>>,
+    mut selected: ResMut<SelectedPitch>,
+) {
+    if selected.0.is_some() {
+        return;
+    }
+    if let Some((handle, _)) = handles.loaded(&pitches).into_iter().next() {
+        selected.0 = Some(handle);
+    }
+}
@@ -0,0 +1,380 @@
+use bevy::core::FixedTimesteps;
+use bevy::math::vec3;
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+
+use crate::input::InputSource;
+use crate::menu;
+use crate::rules::{Kickoff, KickoffStage, MatchClock, MatchEnded, MatchPhase};
+use crate::simulation::{
+    Ball, KickCharge, MatchConfig, MatchEntity, PreviousPosition, Radius, Score, Team,
+    FIXED_TIMESTEP_LABEL, PLAYER_RADIUS, TIMESTEP,
+};
+use crate::{GameState, WINDOW_HEIGHT, WINDOW_WIDTH};
+
+// Assets
+pub const PLAYER_RED_SPRITE: &str = "player_red.png";
+pub const PLAYER_BLUE_SPRITE: &str = "player_blue.png";
+pub const BALL_SPRITE: &str = "ball.png";
+pub const FONT: &str = "fonts/FiraSans-Regular.ttf";
+// How much bigger than the player its halo is.
+pub const HALO_SCALE: f32 = 1.3;
+// Size of the bar above a player that shows how charged its kick is.
+pub const POWER_BAR_WIDTH: f32 = 50.0;
+pub const POWER_BAR_HEIGHT: f32 = 6.0;
+
+#[derive(Component)]
+struct ScoreText;
+
+// Shows the match clock, next to the score.
+#[derive(Component)]
+struct ClockText;
+
+// Counts down to the kickoff, in the middle of the pitch.
+#[derive(Component)]
+struct CountdownText;
+
+// Ring around a player, shown while a person controls the player.
+#[derive(Component)]
+struct Halo(Entity);
+
+// Bar above a player, filling up while the player charges a kick.
+#[derive(Component)]
+struct PowerBar(Entity);
+
+// Announces the result when the match ends.
+#[derive(Component)]
+struct ResultText;
+
+// Everything that is drawn on the screen: the menu, sprites of the simulated
+// entities and the score. Requires DefaultPlugins and PitchPlugin.
+pub struct RenderPlugin;
+
+impl Plugin for RenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(menu::Menu)
+            .add_system_set(SystemSet::on_enter(GameState::InGame).with_system(init_game_system))
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(player_sprites_system)
+                    .with_system(player_size_system)
+                    .with_system(ball_sprite_system)
+                    .with_system(score_text_system)
+                    .with_system(clock_text_system)
+                    .with_system(countdown_text_system)
+                    .with_system(result_text_system),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolation_system
+                    .label(InterpolationLabel)
+                    .after(TransformSystem::TransformPropagate),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, halo_system.after(InterpolationLabel))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                power_bar_system.after(InterpolationLabel),
+            );
+    }
+}
+
+// Initialize the game view.
+fn init_game_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<MatchConfig>,
+) {
+    // Init camera.
+    commands
+        .spawn_bundle(OrthographicCameraBundle::new_2d())
+        .insert(MatchEntity);
+
+    let font = asset_server.load(FONT);
+    let text_style = TextStyle {
+        font,
+        font_size: 60.0,
+        color: Color::WHITE,
+    };
+    let text_alignment = TextAlignment {
+        vertical: VerticalAlign::Center,
+        horizontal: HorizontalAlign::Center,
+    };
+
+    // Show score on the screen (on the bottom left corner)
+    let score_text = String::from("Score: 0–0");
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text {
+                sections: vec![TextSection {
+                    value: score_text,
+                    style: text_style.clone(),
+                }],
+                alignment: text_alignment,
+            },
+            transform: Transform::from_translation(vec3(
+                -WINDOW_WIDTH / 2. + 125.,
+                -WINDOW_HEIGHT / 2. + 50.,
+                2.0,
+            )),
+            global_transform: Default::default(),
+            text_2d_size: Default::default(),
+            text_2d_bounds: Default::default(),
+            visibility: Visibility { is_visible: true },
+        })
+        .insert(ScoreText)
+        .insert(MatchEntity);
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("00:00", text_style.clone(), text_alignment),
+            transform: Transform::from_translation(vec3(
+                -WINDOW_WIDTH / 2. + 360.,
+                -WINDOW_HEIGHT / 2. + 50.,
+                2.0,
+            )),
+            ..Default::default()
+        })
+        .insert(ClockText)
+        .insert(MatchEntity);
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style.clone(), text_alignment),
+            transform: Transform::from_translation(vec3(0.0, 0.0, 2.0)),
+            ..Default::default()
+        })
+        .insert(CountdownText)
+        .insert(MatchEntity);
+
+    // The result is shown at the top, once the match is over.
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::with_section("", text_style, text_alignment),
+            transform: Transform::from_translation(vec3(0.0, WINDOW_HEIGHT / 2. - 60., 2.0)),
+            ..Default::default()
+        })
+        .insert(ResultText)
+        .insert(MatchEntity);
+
+    // The pitch the match is played on, as chosen in the menu or recorded in
+    // the replay.
+    let texture = match &config.pitch_texture {
+        Some(texture) => texture,
+        None => return,
+    };
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: asset_server.load(texture.as_str()),
+            transform: Transform {
+                translation: vec3(0.0, 0.0, 1.0),
+                ..Default::default()
+            },
+
+            ..Default::default()
+        })
+        .insert(MatchEntity);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+struct InterpolationLabel;
+
+// Gives newly spawned players their sprites, and a halo drawn under them.
+fn player_sprites_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    query: Query<(Entity, &Transform, &Team), Added<Team>>,
+) {
+    for (entity, transform, team) in query.iter() {
+        let sprite = match team {
+            Team::Red => PLAYER_RED_SPRITE,
+            Team::Blue => PLAYER_BLUE_SPRITE,
+        };
+        commands.entity(entity).insert_bundle(SpriteBundle {
+            texture: asset_server.load(sprite),
+            transform: *transform,
+            ..Default::default()
+        });
+        commands
+            .spawn_bundle(SpriteBundle {
+                texture: asset_server.load(sprite),
+                sprite: Sprite {
+                    color: Color::rgba(1.0, 1.0, 0.4, 0.6),
+                    ..Default::default()
+                },
+                visibility: Visibility { is_visible: false },
+                ..Default::default()
+            })
+            .insert(Halo(entity))
+            .insert(MatchEntity);
+        commands
+            .spawn_bundle(SpriteBundle {
+                visibility: Visibility { is_visible: false },
+                ..Default::default()
+            })
+            .insert(PowerBar(entity))
+            .insert(MatchEntity);
+    }
+}
+
+// Scales the sprites of players whose radius changed, e.g. goalkeepers in
+// their goal area.
+fn player_size_system(mut query: Query<(&Team, &Radius, &mut Transform), Changed<Radius>>) {
+    for (_, radius, mut transform) in query.iter_mut() {
+        transform.scale = Vec3::splat(radius.0 / PLAYER_RADIUS);
+    }
+}
+
+// Gives a newly spawned ball its sprite.
+fn ball_sprite_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    query: Query<(Entity, &Transform), Added<Ball>>,
+) {
+    for (entity, transform) in query.iter() {
+        commands.entity(entity).insert_bundle(SpriteBundle {
+            texture: asset_server.load(BALL_SPRITE),
+            transform: *transform,
+            ..Default::default()
+        });
+    }
+}
+
+// Keeps the score text in sync with the Score resource.
+fn score_text_system(score: Res<Score>, mut query: Query<&mut Text, With<ScoreText>>) {
+    if !score.is_changed() {
+        return;
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!("Score: {}–{}", score.red, score.blue);
+    }
+}
+
+// Shows the playing time as minutes and seconds, or "Half-time" during the
+// break.
+fn clock_text_system(
+    clock: Option<Res<MatchClock>>,
+    phase: Option<Res<MatchPhase>>,
+    mut query: Query<&mut Text, With<ClockText>>,
+) {
+    let (clock, phase) = match (clock, phase) {
+        (Some(clock), Some(phase)) => (clock, phase),
+        _ => return,
+    };
+    if !clock.is_changed() && !phase.is_changed() {
+        return;
+    }
+
+    let seconds = clock.seconds() as u64;
+    let label = match *phase {
+        MatchPhase::HalfTime => "Half-time".to_string(),
+        MatchPhase::Overtime => format!("OT {:02}:{:02}", seconds / 60, seconds % 60),
+        _ => format!("{:02}:{:02}", seconds / 60, seconds % 60),
+    };
+    for mut text in query.iter_mut() {
+        text.sections[0].value = label.clone();
+    }
+}
+
+fn countdown_text_system(
+    kickoff: Option<Res<Kickoff>>,
+    mut query: Query<&mut Text, With<CountdownText>>,
+) {
+    let kickoff = match kickoff {
+        Some(kickoff) => kickoff,
+        None => return,
+    };
+    if !kickoff.is_changed() {
+        return;
+    }
+
+    let label = match kickoff.stage {
+        KickoffStage::Countdown(left) => format!("{}", (left as f64 * TIMESTEP).ceil() as u64),
+        _ => String::new(),
+    };
+    for mut text in query.iter_mut() {
+        text.sections[0].value = label.clone();
+    }
+}
+
+fn result_text_system(
+    mut match_ended_events: EventReader<MatchEnded>,
+    mut query: Query<&mut Text, With<ResultText>>,
+) {
+    for MatchEnded { winner } in match_ended_events.iter() {
+        for mut text in query.iter_mut() {
+            text.sections[0].value = match winner {
+                Some(Team::Red) => "Red Wins!".to_string(),
+                Some(Team::Blue) => "Blue Wins!".to_string(),
+                None => "Draw!".to_string(),
+            };
+        }
+    }
+}
+
+// Keeps the halos under their players, visible while a person controls them.
+fn halo_system(
+    query_players: Query<(&GlobalTransform, &InputSource), Without<Halo>>,
+    mut query: Query<(&Halo, &mut GlobalTransform, &mut Visibility)>,
+) {
+    for (halo, mut global_transform, mut visibility) in query.iter_mut() {
+        let (player_transform, source) = match query_players.get(halo.0) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        global_transform.translation = player_transform.translation - Vec3::Z * 0.5;
+        global_transform.scale = player_transform.scale * HALO_SCALE;
+        visibility.is_visible = source.is_human();
+    }
+}
+
+// Fills the power bars of the players charging a kick, from yellow to red,
+// and hides the others.
+fn power_bar_system(
+    query_players: Query<(&GlobalTransform, &KickCharge), Without<PowerBar>>,
+    mut query: Query<(
+        &PowerBar,
+        &mut Sprite,
+        &mut GlobalTransform,
+        &mut Visibility,
+    )>,
+) {
+    for (bar, mut sprite, mut global_transform, mut visibility) in query.iter_mut() {
+        let (player_transform, charge) = match query_players.get(bar.0) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        visibility.is_visible = charge.ticks > 0;
+        let power = charge.power();
+        let width = POWER_BAR_WIDTH * power;
+        sprite.custom_size = Some(Vec2::new(width, POWER_BAR_HEIGHT));
+        sprite.color = Color::rgb(1.0, 1.0 - power, 0.0);
+        // The bar grows from its left end, just above the player.
+        global_transform.translation = player_transform.translation
+            + vec3(
+                (width - POWER_BAR_WIDTH) / 2.0,
+                PLAYER_RADIUS * player_transform.scale.y + POWER_BAR_HEIGHT,
+                1.0,
+            );
+    }
+}
+
+// The simulation runs at a fixed rate, so between two simulation steps draw
+// the entities at a position interpolated between the last two steps.
+fn interpolation_system(
+    state: Res<State<GameState>>,
+    timesteps: Res<FixedTimesteps>,
+    mut query: Query<(&Transform, &PreviousPosition, &mut GlobalTransform)>,
+) {
+    // The simulation doesn't step while the match is paused or over, so
+    // there is nothing to interpolate.
+    if *state.current() != GameState::InGame {
+        return;
+    }
+    let alpha = match timesteps.get(FIXED_TIMESTEP_LABEL) {
+        Some(timestep) => timestep.overstep_percentage() as f32,
+        None => return,
+    };
+    for (transform, previous, mut global_transform) in query.iter_mut() {
+        global_transform.translation = previous.0.lerp(transform.translation, alpha);
+    }
+}
@@ -0,0 +1,230 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bevy::app::AppExit;
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::input::{InputSource, InputSources, PlayerInput};
+use crate::pitch::PitchHandles;
+use crate::rules::match_running;
+use crate::simulation::{MatchConfig, PlayerId, SimulationLabel, SimulationStage, SimulationTick};
+use crate::{change_state, GameState};
+
+// Bumped whenever the replay format or the simulation changes in a way that
+// makes older replays play out differently.
+pub const REPLAY_VERSION: u32 = 1;
+
+// Inputs of all players in one simulation step, in the order of their ids.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TickInputs(pub Vec<PlayerInput>);
+
+// A recorded match: its config and the inputs of every step, in order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub config: MatchConfig,
+    pub inputs: Vec<TickInputs>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse(ron::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "cannot access replay file: {}", err),
+            ReplayError::Parse(err) => write!(f, "invalid replay file: {}", err),
+            ReplayError::UnsupportedVersion(version) => write!(
+                f,
+                "replay version {} is not supported (expected {})",
+                version, REPLAY_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+impl From<ron::Error> for ReplayError {
+    fn from(err: ron::Error) -> Self {
+        ReplayError::Parse(err)
+    }
+}
+
+impl Replay {
+    pub fn new(config: MatchConfig) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            config,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let replay: Replay = ron::from_str(&fs::read_to_string(path)?)?;
+        if replay.version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(replay.version));
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        let text = ron::ser::to_string_pretty(self, Default::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+// What ReplayPlugin does with the matches. For Playback, a Replay resource
+// has to be inserted too.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    Record(PathBuf),
+    Playback,
+}
+
+// Records the inputs of a match into a replay file, or feeds the inputs from
+// a replay back into the players instead of their usual input sources.
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayMode>()
+            .add_startup_system(replay_input_sources_system)
+            .add_system_set(
+                SystemSet::on_update(GameState::InMenu).with_system(start_playback_system),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::InGame).with_system(start_recording_system),
+            )
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .with_run_criteria(match_running)
+                    .with_system(
+                        replay_system
+                            .label(SimulationLabel::Replay)
+                            .after(SimulationLabel::AiInput)
+                            .before(SimulationLabel::Switch),
+                    ),
+            )
+            .add_system_to_stage(CoreStage::Last, save_replay_system);
+    }
+}
+
+// During playback nobody controls the players.
+fn replay_input_sources_system(mode: Res<ReplayMode>, mut input_sources: ResMut<InputSources>) {
+    if *mode == ReplayMode::Playback {
+        input_sources.red = InputSource::Replay;
+        input_sources.blue = InputSource::Replay;
+    }
+}
+
+// A replay starts playing without going through the menu, as soon as the
+// pitches and the recorded pitch image are loaded (when there is an asset
+// server at all).
+fn start_playback_system(
+    mode: Res<ReplayMode>,
+    config: Res<MatchConfig>,
+    asset_server: Option<Res<AssetServer>>,
+    pitch_handles: Option<Res<PitchHandles>>,
+    mut app_state: ResMut<State<GameState>>,
+    mut texture: Local<Option<Handle<Image>>>,
+    mut started: Local<bool>,
+) {
+    if *mode != ReplayMode::Playback || *started {
+        return;
+    }
+    if let Some(asset_server) = asset_server {
+        if texture.is_none() {
+            *texture = config
+                .pitch_texture
+                .as_ref()
+                .map(|path| asset_server.load(path.as_str()));
+        }
+        let pitches = pitch_handles
+            .iter()
+            .flat_map(|handles| handles.0.iter().map(|handle| handle.id));
+        let handles = pitches.chain(texture.iter().map(|handle| handle.id));
+        if let LoadState::NotLoaded | LoadState::Loading =
+            asset_server.get_group_load_state(handles)
+        {
+            return;
+        }
+    }
+    *started = true;
+    change_state(app_state.set(GameState::InGame));
+}
+
+fn start_recording_system(mut commands: Commands, mode: Res<ReplayMode>, config: Res<MatchConfig>) {
+    if let ReplayMode::Record(_) = *mode {
+        commands.insert_resource(Replay::new(config.clone()));
+    }
+}
+
+// Stores the inputs of the current step, or replaces them with the recorded ones.
+fn replay_system(
+    mode: Res<ReplayMode>,
+    replay: Option<ResMut<Replay>>,
+    tick: Res<SimulationTick>,
+    mut query: Query<(&PlayerId, &mut PlayerInput)>,
+) {
+    let mut replay = match replay {
+        Some(replay) => replay,
+        None => return,
+    };
+    let mut players: Vec<_> = query.iter_mut().collect();
+    players.sort_by_key(|(id, _)| **id);
+
+    match *mode {
+        ReplayMode::Off => {}
+        ReplayMode::Record(_) => {
+            let inputs = players.iter().map(|(_, input)| **input).collect();
+            replay.inputs.push(TickInputs(inputs));
+        }
+        ReplayMode::Playback => {
+            // The first step of a match is tick 1. Once the replay is over
+            // the players just stand still.
+            let inputs = replay.inputs.get(tick.0 as usize - 1);
+            for (i, (_, input)) in players.iter_mut().enumerate() {
+                **input = inputs
+                    .and_then(|inputs| inputs.0.get(i))
+                    .copied()
+                    .unwrap_or_default();
+            }
+        }
+    }
+}
+
+// Writes the recording to disk when the game is closed.
+fn save_replay_system(
+    mut app_exit_events: EventReader<AppExit>,
+    mode: Res<ReplayMode>,
+    replay: Option<Res<Replay>>,
+) {
+    if app_exit_events.iter().next().is_none() {
+        return;
+    }
+    if let (ReplayMode::Record(path), Some(replay)) = (&*mode, replay) {
+        match replay.save(path) {
+            Ok(()) => info!("Replay saved to {}", path.display()),
+            Err(err) => error!("Failed to save replay to {}: {}", path.display(), err),
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::pitch::PitchGeometry;
+use crate::simulation::{
+    reset_body, Ball, HomePosition, PreviousPosition, Radius, Score, Team, Velocity, TIMESTEP,
+};
+use crate::GameState;
+
+// Length of the break between the two halves.
+pub const HALF_TIME_BREAK_SECONDS: f64 = 3.0;
+// Length of the 3-2-1 countdown before a kickoff.
+pub const KICKOFF_COUNTDOWN_SECONDS: f64 = 3.0;
+// Team kicking off the match. The other team kicks off the second half.
+pub const FIRST_KICKOFF: Team = Team::Red;
+
+// Which way a kicked ball goes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KickControl {
+    // Straight away from the player, through the point of contact.
+    #[default]
+    Classic,
+    // Between the contact direction and the direction the player is moving
+    // in, so the player can place the shot.
+    Aimed,
+}
+
+impl KickControl {
+    pub const ALL: [KickControl; 2] = [KickControl::Classic, KickControl::Aimed];
+}
+
+// How a match is won and played. Chosen in the menu from MatchRules::PRESETS,
+// the kick control separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchRules {
+    // The first team to score this many goals wins.
+    pub score_limit: Option<i32>,
+    // Length of the match in seconds. When the time is up, the leading team
+    // wins.
+    pub time_limit: Option<u32>,
+    // When the time is up with a draw, the next goal wins.
+    pub golden_goal: bool,
+    // When the time is up with a draw and there is no golden goal, the match
+    // ends in a draw. Otherwise it goes on until the next goal.
+    pub draw_allowed: bool,
+    // The time is split into this many halves (1 or 2). The teams switch
+    // sides at half-time.
+    pub halves: u32,
+    #[serde(default)]
+    pub kick_control: KickControl,
+}
+
+impl MatchRules {
+    pub const PRESETS: [MatchRules; 5] = [
+        MatchRules {
+            score_limit: Some(3),
+            time_limit: None,
+            golden_goal: false,
+            draw_allowed: false,
+            halves: 1,
+            kick_control: KickControl::Classic,
+        },
+        MatchRules {
+            score_limit: Some(5),
+            time_limit: None,
+            golden_goal: false,
+            draw_allowed: false,
+            halves: 1,
+            kick_control: KickControl::Classic,
+        },
+        MatchRules {
+            score_limit: None,
+            time_limit: Some(180),
+            golden_goal: true,
+            draw_allowed: false,
+            halves: 2,
+            kick_control: KickControl::Classic,
+        },
+        MatchRules {
+            score_limit: None,
+            time_limit: Some(180),
+            golden_goal: false,
+            draw_allowed: true,
+            halves: 2,
+            kick_control: KickControl::Classic,
+        },
+        MatchRules {
+            score_limit: Some(5),
+            time_limit: Some(300),
+            golden_goal: true,
+            draw_allowed: false,
+            halves: 2,
+            kick_control: KickControl::Classic,
+        },
+    ];
+
+    // Short description for the menu, e.g. "First to 5, 5 min".
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(score_limit) = self.score_limit {
+            parts.push(format!("First to {}", score_limit));
+        }
+        if let Some(time_limit) = self.time_limit {
+            parts.push(format!("{} min", time_limit / 60));
+            if self.halves > 1 {
+                parts.push(format!("{} halves", self.halves));
+            }
+            if self.golden_goal {
+                parts.push("golden goal".to_string());
+            } else if self.draw_allowed {
+                parts.push("draws".to_string());
+            }
+        }
+        parts.join(", ")
+    }
+
+    // Steps of playing time after which the time is up.
+    pub fn time_limit_ticks(&self) -> Option<u64> {
+        self.time_limit
+            .map(|seconds| (seconds as f64 / TIMESTEP).round() as u64)
+    }
+
+    pub fn half_length_ticks(&self) -> Option<u64> {
+        self.time_limit_ticks()
+            .map(|ticks| ticks / self.halves.max(1) as u64)
+    }
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        MatchRules::PRESETS[0]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchPhase {
+    Playing,
+    // Break between the halves, nothing moves.
+    HalfTime,
+    // The time is up with a draw, the next goal wins.
+    Overtime,
+    // The simulation stops.
+    Ended,
+}
+
+// Playing time of the match. It only runs while the ball is in play: it stops
+// at a goal and starts again when the ball is kicked off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchClock {
+    // Steps of playing time.
+    pub elapsed: u64,
+    // The half being played, starting at 1.
+    pub half: u32,
+    // Steps left of the half-time break.
+    pub break_left: u64,
+}
+
+impl MatchClock {
+    pub fn new() -> Self {
+        Self {
+            elapsed: 0,
+            half: 1,
+            break_left: 0,
+        }
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.elapsed as f64 * TIMESTEP
+    }
+}
+
+impl Default for MatchClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KickoffStage {
+    // Steps left until the players may move.
+    Countdown(u64),
+    // The players may move, but the team not kicking off has to stay outside
+    // the centre circle until the ball is touched.
+    Waiting,
+    // The ball is in play.
+    Taken,
+}
+
+// The kickoff at the start of each half and after every goal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Kickoff {
+    // The team kicking off.
+    pub team: Team,
+    pub stage: KickoffStage,
+}
+
+impl Kickoff {
+    pub fn new(team: Team) -> Self {
+        Self {
+            team,
+            stage: KickoffStage::Countdown((KICKOFF_COUNTDOWN_SECONDS / TIMESTEP).round() as u64),
+        }
+    }
+}
+
+// Sent once when the match is over. The winner is None for a draw.
+pub struct MatchEnded {
+    pub winner: Option<Team>,
+}
+
+// Run criteria of the simulation: it runs in game until the match ends.
+pub fn match_running(state: Res<State<GameState>>, phase: Option<Res<MatchPhase>>) -> ShouldRun {
+    let ended = phase.is_none_or(|phase| *phase == MatchPhase::Ended);
+    if *state.current() == GameState::InGame && !ended {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+// Run criteria of the physics: like match_running, but it also stops for the
+// half-time break and the kickoff countdown.
+pub fn physics_running(
+    state: Res<State<GameState>>,
+    phase: Option<Res<MatchPhase>>,
+    kickoff: Option<Res<Kickoff>>,
+) -> ShouldRun {
+    let playing =
+        phase.is_some_and(|phase| *phase == MatchPhase::Playing || *phase == MatchPhase::Overtime);
+    let counting_down =
+        kickoff.is_some_and(|kickoff| matches!(kickoff.stage, KickoffStage::Countdown(_)));
+    if *state.current() == GameState::InGame && playing && !counting_down {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+// Counts down to the kickoff and waits until the ball is touched.
+pub fn kickoff_system(
+    phase: Res<MatchPhase>,
+    mut kickoff: ResMut<Kickoff>,
+    query_ball: Query<&Velocity, With<Ball>>,
+) {
+    if *phase != MatchPhase::Playing && *phase != MatchPhase::Overtime {
+        return;
+    }
+    match kickoff.stage {
+        KickoffStage::Countdown(left) => {
+            kickoff.stage = if left > 1 {
+                KickoffStage::Countdown(left - 1)
+            } else {
+                KickoffStage::Waiting
+            };
+        }
+        KickoffStage::Waiting => {
+            let ball_moving = query_ball
+                .iter()
+                .any(|velocity| velocity.x != 0.0 || velocity.y != 0.0);
+            if ball_moving {
+                kickoff.stage = KickoffStage::Taken;
+            }
+        }
+        KickoffStage::Taken => {}
+    }
+}
+
+// Keeps the players of the team not kicking off outside the centre circle
+// until the ball is touched.
+pub fn kickoff_hold_system(
+    kickoff: Res<Kickoff>,
+    geometry: Res<PitchGeometry>,
+    mut query: Query<(&Team, &mut Velocity, &mut Transform, &Radius)>,
+) {
+    if kickoff.stage != KickoffStage::Waiting {
+        return;
+    }
+    for (team, mut velocity, mut transform, radius) in query.iter_mut() {
+        if *team == kickoff.team {
+            continue;
+        }
+        let position = transform.translation.truncate();
+        let min_distance = geometry.centre_circle_radius + radius.0;
+        let distance = position.length();
+        if distance >= min_distance {
+            continue;
+        }
+        // Move the player to the edge of the circle and stop it moving inwards.
+        let normal = if distance > 0.0 {
+            position / distance
+        } else {
+            Vec2::new(-kickoff.team.side(), 0.0)
+        };
+        let edge = normal * min_distance;
+        transform.translation.x = edge.x;
+        transform.translation.y = edge.y;
+        let v = Vec2::new(velocity.x, velocity.y);
+        let inwards = v.dot(normal).min(0.0);
+        velocity.x -= inwards * normal.x;
+        velocity.y -= inwards * normal.y;
+    }
+}
+
+// Advances the match clock while the ball is in play, and the half-time break.
+pub fn clock_system(
+    mut clock: ResMut<MatchClock>,
+    mut phase: ResMut<MatchPhase>,
+    kickoff: Res<Kickoff>,
+) {
+    match *phase {
+        MatchPhase::Playing | MatchPhase::Overtime => {
+            if kickoff.stage == KickoffStage::Taken {
+                clock.elapsed += 1;
+            }
+        }
+        MatchPhase::HalfTime => {
+            clock.break_left = clock.break_left.saturating_sub(1);
+            if clock.break_left == 0 {
+                *phase = MatchPhase::Playing;
+            }
+        }
+        MatchPhase::Ended => {}
+    }
+}
+
+// Starts the half-time break when the first half is over. The teams switch
+// sides and line up on their new sides right away.
+pub fn half_time_system(
+    rules: Res<MatchRules>,
+    mut clock: ResMut<MatchClock>,
+    mut phase: ResMut<MatchPhase>,
+    mut geometry: ResMut<PitchGeometry>,
+    mut kickoff: ResMut<Kickoff>,
+    mut query_ball: Query<(&mut Velocity, &mut Transform, &mut PreviousPosition), With<Ball>>,
+    mut query_players: Query<
+        (
+            &mut Velocity,
+            &mut Transform,
+            &mut PreviousPosition,
+            &mut HomePosition,
+        ),
+        Without<Ball>,
+    >,
+) {
+    let half_length = match rules.half_length_ticks() {
+        Some(half_length) => half_length,
+        None => return,
+    };
+    if *phase != MatchPhase::Playing
+        || clock.half >= rules.halves
+        || clock.elapsed < half_length * clock.half as u64
+    {
+        return;
+    }
+
+    *phase = MatchPhase::HalfTime;
+    clock.half += 1;
+    clock.break_left = (HALF_TIME_BREAK_SECONDS / TIMESTEP).round() as u64;
+
+    *kickoff = Kickoff::new(FIRST_KICKOFF.opponent());
+    geometry.switch_sides();
+    for (mut velocity, mut transform, mut previous) in query_ball.iter_mut() {
+        let centre = Vec3::new(0.0, 0.0, transform.translation.z);
+        reset_body(&mut velocity, &mut transform, &mut previous, centre);
+    }
+    for (mut velocity, mut transform, mut previous, mut home) in query_players.iter_mut() {
+        home.0.x = -home.0.x;
+        reset_body(&mut velocity, &mut transform, &mut previous, home.0);
+    }
+}
+
+fn leader(score: &Score) -> Option<Team> {
+    if score.red > score.blue {
+        Some(Team::Red)
+    } else if score.blue > score.red {
+        Some(Team::Blue)
+    } else {
+        None
+    }
+}
+
+// Ends the match, or starts the overtime, as the rules say.
+pub fn match_end_system(
+    rules: Res<MatchRules>,
+    clock: Res<MatchClock>,
+    score: Res<Score>,
+    mut phase: ResMut<MatchPhase>,
+    mut match_ended_events: EventWriter<MatchEnded>,
+) {
+    // Some(winner) when the match is over, None while it goes on.
+    let result = match *phase {
+        MatchPhase::Ended | MatchPhase::HalfTime => return,
+        MatchPhase::Overtime => leader(&score).map(Some),
+        MatchPhase::Playing => {
+            let winner = rules.score_limit.and_then(|score_limit| {
+                Team::ALL
+                    .into_iter()
+                    .find(|team| score.get(*team) >= score_limit)
+            });
+            let time_is_up = rules
+                .time_limit_ticks()
+                .is_some_and(|limit| clock.elapsed >= limit);
+            if winner.is_some() {
+                Some(winner)
+            } else if !time_is_up {
+                None
+            } else {
+                match leader(&score) {
+                    Some(winner) => Some(Some(winner)),
+                    None if rules.draw_allowed && !rules.golden_goal => Some(None),
+                    None => {
+                        *phase = MatchPhase::Overtime;
+                        None
+                    }
+                }
+            }
+        }
+    };
+
+    if let Some(winner) = result {
+        *phase = MatchPhase::Ended;
+        match_ended_events.send(MatchEnded { winner });
+    }
+}
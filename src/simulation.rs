@@ -0,0 +1,1088 @@
+use bevy::core::FixedTimestep;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ai::{ai_input_system, AiMemory, TEAMMATE_DIFFICULTY};
+use crate::collision::{collision_system, sweep_circle_segment};
+use crate::determinism::SimulationRng;
+use crate::input::{
+    assign_gamepads_system, gamepad_input_system, keyboard_input_system, GamepadAssignments,
+    InputSource, InputSources, PlayerInput, SwitchHeld,
+};
+use crate::pitch::{PitchGeometry, GOAL_AREA_DEPTH};
+use crate::rules::{
+    clock_system, half_time_system, kickoff_hold_system, kickoff_system, match_end_system,
+    match_running, physics_running, KickControl, Kickoff, MatchClock, MatchEnded, MatchPhase,
+    MatchRules, FIRST_KICKOFF,
+};
+use crate::GameState;
+
+// Constants
+pub const MAX_SPEED: f32 = 3.0;
+pub const PLAYER_RADIUS: f32 = 25.0;
+pub const BALL_RADIUS: f32 = 10.0;
+// Distance of the first player of each team from the centre line.
+pub const INITIAL_X: f32 = 200.0;
+// Vertical distance between the players of a team when they line up.
+pub const FORMATION_SPACING: f32 = 150.0;
+pub const MAX_TEAM_SIZE: u32 = 4;
+// Speed a fully charged kick gives the ball.
+pub const KICK_STRENGTH: f32 = 8.0;
+// How many steps kick has to be held for a fully charged kick.
+pub const KICK_CHARGE_TICKS: u32 = 45;
+// Even a kick released right away has this part of the full strength.
+pub const MIN_KICK_POWER: f32 = 0.3;
+// How far from touching the ball a player can still kick it.
+pub const KICK_MARGIN: f32 = 5.0;
+// In aimed kicks, how much of the direction comes from the movement of the
+// player rather than from the point of contact, from 0 to 1.
+pub const AIM_WEIGHT: f32 = 0.5;
+// Steps after a kick before the player can charge the next one.
+pub const KICK_COOLDOWN_TICKS: u32 = 20;
+// Switching players picks a teammate in the direction of movement when the
+// direction to it is this close to it, as the cosine of the angle between them.
+pub const SWITCH_ALIGNMENT: f32 = 0.5;
+// How much a player without input slows down in every step.
+pub const PLAYER_FRICTION: f32 = 0.05;
+
+// The simulation always advances in steps of this length (in seconds),
+// independently of the frame rate. Velocities are in pixels per step.
+pub const TIMESTEP: f64 = 1.0 / 60.0;
+pub const FIXED_TIMESTEP_LABEL: &str = "simulation_timestep";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub struct SimulationStage;
+
+// Every simulation system has a label and runs after the previous one, so the
+// order of a step never depends on the scheduler.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum SimulationLabel {
+    Tick,
+    Input,
+    GamepadInput,
+    AiInput,
+    Replay,
+    Switch,
+    PlayerMovement,
+    Movement,
+    GoalkeeperRadius,
+    Kick,
+    Collision,
+    EdgeCollision,
+    BallFriction,
+    Goal,
+    Kickoff,
+    KickoffHold,
+    Clock,
+    HalfTime,
+    MatchEnd,
+}
+
+// Settings a match is started with. Together with the inputs of every step
+// they fully determine the match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchConfig {
+    pub seed: u64,
+    // Number of players in each team, from 1 to MAX_TEAM_SIZE.
+    pub team_size: u32,
+    pub physics: PhysicsConfig,
+    pub pitch: PitchGeometry,
+    // Image of the pitch, relative to the assets folder. Without it no pitch
+    // is drawn.
+    #[serde(default)]
+    pub pitch_texture: Option<String>,
+    pub rules: MatchRules,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            team_size: 1,
+            physics: PhysicsConfig::default(),
+            pitch: PitchGeometry::default(),
+            pitch_texture: None,
+            rules: MatchRules::default(),
+        }
+    }
+}
+
+// Mass and bounciness of the bodies, so each pitch or game mode can have its
+// own feel.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PhysicsConfig {
+    pub player_mass: f32,
+    pub player_restitution: f32,
+    pub ball_mass: f32,
+    pub ball_restitution: f32,
+    // How much the ball slows down in every step.
+    pub ball_friction: f32,
+    // Restitution of the walls of the pitch.
+    pub wall_restitution: f32,
+    // Radius of human controlled goalkeepers inside their goal area, if they
+    // are bigger there.
+    pub goalkeeper_radius: Option<f32>,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        Self {
+            player_mass: 3.0,
+            player_restitution: 0.5,
+            ball_mass: 1.0,
+            ball_restitution: 0.6,
+            ball_friction: 0.05,
+            wall_restitution: 1.0,
+            goalkeeper_radius: None,
+        }
+    }
+}
+
+// Number of simulation steps since the start of the match.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SimulationTick(pub u64);
+
+// What advances the simulation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SimulationClock {
+    // One step for every elapsed TIMESTEP of real time.
+    #[default]
+    Realtime,
+    // Only as many steps as requested, regardless of the time. Each update
+    // of the app runs the requested steps and leaves 0. Used by headless
+    // harnesses, see step_simulation.
+    Manual(u32),
+}
+
+// Runs exactly the given number of simulation steps in one update of the app,
+// however much real time passes. The app keeps a manual clock afterwards.
+pub fn step_simulation(app: &mut App, steps: u32) {
+    app.insert_resource(SimulationClock::Manual(steps));
+    app.update();
+}
+
+// Takes the decision of the fixed timestep when the clock is real time, and
+// otherwise runs the requested steps.
+fn simulation_clock_criteria(
+    In(realtime): In<ShouldRun>,
+    mut clock: ResMut<SimulationClock>,
+) -> ShouldRun {
+    match *clock {
+        SimulationClock::Realtime => realtime,
+        SimulationClock::Manual(0) => ShouldRun::No,
+        SimulationClock::Manual(steps) => {
+            *clock = SimulationClock::Manual(steps - 1);
+            ShouldRun::YesAndCheckAgain
+        }
+    }
+}
+
+// Components
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Team {
+    Red,
+    Blue,
+}
+
+impl Team {
+    pub const ALL: [Team; 2] = [Team::Red, Team::Blue];
+
+    // Direction from the centre to the half the team starts in.
+    pub fn side(self) -> f32 {
+        match self {
+            Team::Red => -1.0,
+            Team::Blue => 1.0,
+        }
+    }
+
+    pub fn opponent(self) -> Team {
+        match self {
+            Team::Red => Team::Blue,
+            Team::Blue => Team::Red,
+        }
+    }
+}
+
+// Unique number of a player within a match. Players are always processed in
+// the order of their ids, to keep the simulation deterministic.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PlayerId(pub u32);
+
+// Where the player lines up after a goal.
+#[derive(Component)]
+pub struct HomePosition(pub Vec3);
+
+#[derive(Component)]
+pub struct Velocity {
+    pub x: f32,
+    pub y: f32,
+}
+
+// Translation before the last simulation step, used to interpolate rendering
+// between two steps.
+#[derive(Component)]
+pub struct PreviousPosition(pub Vec3);
+
+#[derive(Component)]
+pub struct Ball;
+
+// What a player does in the team. With two or more players in a team, the
+// last one is the goalkeeper.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Outfield,
+    Goalkeeper,
+}
+
+// A kick being charged. It is charged while kick is held and taken when kick
+// is released.
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KickCharge {
+    // Steps kick has been held, up to KICK_CHARGE_TICKS.
+    pub ticks: u32,
+    // Steps until the next kick can be charged.
+    pub cooldown: u32,
+}
+
+impl KickCharge {
+    // How much of the full strength the kick has, from 0 to 1.
+    pub fn power(&self) -> f32 {
+        self.ticks as f32 / KICK_CHARGE_TICKS as f32
+    }
+}
+
+// A goal post. Posts never move, the other bodies bounce off them.
+#[derive(Component)]
+pub struct Post;
+
+// Everything spawned for a match: the bodies and how they are drawn. It is
+// despawned when the match is over.
+#[derive(Component)]
+pub struct MatchEntity;
+
+#[derive(Component)]
+pub struct Radius(pub f32);
+
+// Bodies without Mass weigh 1. An infinite mass makes the body static, it is
+// never pushed by collisions.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Mass(pub f32);
+
+impl Mass {
+    pub const STATIC: Mass = Mass(f32::INFINITY);
+
+    pub fn inverse(self) -> f32 {
+        if self.0.is_finite() {
+            1.0 / self.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Default for Mass {
+    fn default() -> Self {
+        Mass(1.0)
+    }
+}
+
+// How much of the speed is kept when bouncing off another body, from 0 (none)
+// to 1 (all). Bodies without Restitution use 0.5. When two bodies collide
+// the bouncier one wins.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Restitution(pub f32);
+
+impl Default for Restitution {
+    fn default() -> Self {
+        Restitution(0.5)
+    }
+}
+
+#[derive(Component)]
+pub struct Score {
+    pub red: i32,
+    pub blue: i32,
+}
+
+impl Score {
+    pub fn get(&self, team: Team) -> i32 {
+        match team {
+            Team::Red => self.red,
+            Team::Blue => self.blue,
+        }
+    }
+}
+
+// Gameplay without any rendering, so a match can be run under MinimalPlugins.
+// The app is expected to add the GameState state itself.
+// All gameplay systems run in SimulationStage, which is executed once for
+// every elapsed TIMESTEP (possibly several times, or not at all, in a frame),
+// or as often as requested with SimulationClock::Manual.
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        // Keyboard and gamepad input is read even when there is no InputPlugin
+        // (e.g. when a bot presses the keys in a headless run).
+        app.init_resource::<Input<KeyCode>>()
+            .init_resource::<Gamepads>()
+            .init_resource::<Axis<GamepadAxis>>()
+            .init_resource::<Input<GamepadButton>>()
+            .init_resource::<GamepadAssignments>()
+            .init_resource::<InputSources>()
+            .init_resource::<MatchConfig>()
+            .init_resource::<SimulationClock>()
+            .add_event::<MatchEnded>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::InGame)
+                    .with_system(init_score_system)
+                    .with_system(init_determinism_system)
+                    .with_system(init_pitch_system)
+                    .with_system(init_rules_system)
+                    .with_system(spawn_players_system)
+                    .with_system(spawn_ball_system)
+                    .with_system(spawn_posts_system),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::InGame).with_system(despawn_match_system))
+            .add_system_to_stage(CoreStage::PreUpdate, assign_gamepads_system)
+            .add_stage_after(
+                CoreStage::Update,
+                SimulationStage,
+                SystemStage::parallel()
+                    .with_run_criteria(
+                        FixedTimestep::step(TIMESTEP)
+                            .with_label(FIXED_TIMESTEP_LABEL)
+                            .chain(simulation_clock_criteria),
+                    )
+                    .with_system_set(State::<GameState>::get_driver()),
+            )
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .with_run_criteria(match_running)
+                    .with_system(tick_system.label(SimulationLabel::Tick))
+                    .with_system(
+                        keyboard_input_system
+                            .label(SimulationLabel::Input)
+                            .after(SimulationLabel::Tick),
+                    )
+                    .with_system(
+                        gamepad_input_system
+                            .label(SimulationLabel::GamepadInput)
+                            .after(SimulationLabel::Input),
+                    )
+                    .with_system(
+                        ai_input_system
+                            .label(SimulationLabel::AiInput)
+                            .after(SimulationLabel::GamepadInput),
+                    )
+                    .with_system(
+                        switch_player_system
+                            .label(SimulationLabel::Switch)
+                            .after(SimulationLabel::AiInput),
+                    )
+                    .with_system(
+                        kickoff_system
+                            .label(SimulationLabel::Kickoff)
+                            .after(SimulationLabel::Goal),
+                    )
+                    .with_system(
+                        clock_system
+                            .label(SimulationLabel::Clock)
+                            .after(SimulationLabel::Kickoff),
+                    )
+                    .with_system(
+                        half_time_system
+                            .label(SimulationLabel::HalfTime)
+                            .after(SimulationLabel::Clock),
+                    )
+                    .with_system(
+                        match_end_system
+                            .label(SimulationLabel::MatchEnd)
+                            .after(SimulationLabel::HalfTime),
+                    ),
+            )
+            // Nothing moves during the half-time break and kickoff countdowns.
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .with_run_criteria(physics_running)
+                    .with_system(
+                        player_movement_system
+                            .label(SimulationLabel::PlayerMovement)
+                            .after(SimulationLabel::Switch),
+                    )
+                    .with_system(
+                        movement_system
+                            .label(SimulationLabel::Movement)
+                            .after(SimulationLabel::PlayerMovement),
+                    )
+                    .with_system(
+                        goalkeeper_radius_system
+                            .label(SimulationLabel::GoalkeeperRadius)
+                            .after(SimulationLabel::Movement),
+                    )
+                    .with_system(
+                        kick_system
+                            .label(SimulationLabel::Kick)
+                            .after(SimulationLabel::GoalkeeperRadius),
+                    )
+                    .with_system(
+                        collision_system
+                            .label(SimulationLabel::Collision)
+                            .after(SimulationLabel::Kick),
+                    )
+                    .with_system(
+                        edge_collision_system
+                            .label(SimulationLabel::EdgeCollision)
+                            .after(SimulationLabel::Collision),
+                    )
+                    .with_system(
+                        kickoff_hold_system
+                            .label(SimulationLabel::KickoffHold)
+                            .after(SimulationLabel::EdgeCollision),
+                    )
+                    .with_system(
+                        control_ball_velocity
+                            .label(SimulationLabel::BallFriction)
+                            .after(SimulationLabel::KickoffHold),
+                    )
+                    .with_system(
+                        goal_system
+                            .label(SimulationLabel::Goal)
+                            .after(SimulationLabel::BallFriction),
+                    ),
+            );
+    }
+}
+
+// Score as a resource.
+fn init_score_system(mut commands: Commands) {
+    commands.insert_resource(Score { red: 0, blue: 0 });
+}
+
+// Restarts the step counter and the random number generator, so every match
+// started with the same seed and inputs plays out the same way.
+fn init_determinism_system(mut commands: Commands, config: Res<MatchConfig>) {
+    commands.insert_resource(SimulationTick(0));
+    commands.insert_resource(SimulationRng::new(config.seed));
+}
+
+fn init_pitch_system(mut commands: Commands, config: Res<MatchConfig>) {
+    commands.insert_resource(config.pitch.clone());
+}
+
+fn init_rules_system(mut commands: Commands, config: Res<MatchConfig>) {
+    commands.insert_resource(config.rules);
+    commands.insert_resource(MatchPhase::Playing);
+    commands.insert_resource(MatchClock::new());
+    commands.insert_resource(Kickoff::new(FIRST_KICKOFF));
+}
+
+// Advances the step counter.
+pub fn tick_system(mut tick: ResMut<SimulationTick>) {
+    tick.0 += 1;
+}
+
+// Where the index-th player of a team of team_size players lines up.
+pub fn home_position(team: Team, index: u32, team_size: u32) -> Vec3 {
+    // Every second player stands a bit further back.
+    let x = team.side() * (INITIAL_X + 100.0 * (index % 2) as f32);
+    let y = (index as f32 - (team_size - 1) as f32 / 2.0) * FORMATION_SPACING;
+    Vec3::new(x, y, 5.0)
+}
+
+// Spawns the players of both teams. Only the first player of each team gets
+// the team's input source. The AI plays with the rest, unless nobody controls
+// the team. Goalkeepers start in their goal area.
+fn spawn_players_system(
+    mut commands: Commands,
+    config: Res<MatchConfig>,
+    input_sources: Res<InputSources>,
+) {
+    let team_size = config.team_size.clamp(1, MAX_TEAM_SIZE);
+    let mut id = 0;
+    for team in Team::ALL {
+        for index in 0..team_size {
+            let role = if team_size >= 2 && index == team_size - 1 {
+                Role::Goalkeeper
+            } else {
+                Role::Outfield
+            };
+            let goal = config
+                .pitch
+                .goals
+                .iter()
+                .find(|goal| goal.defending == team);
+            let position = match (role, goal) {
+                (Role::Goalkeeper, Some(goal)) => {
+                    (goal.centre() + goal.inward() * GOAL_AREA_DEPTH / 2.0).extend(5.0)
+                }
+                _ => home_position(team, index, team_size),
+            };
+            let input_source = match input_sources.get(team) {
+                source if index == 0 => source.clone(),
+                InputSource::Ai(difficulty) => InputSource::Ai(*difficulty),
+                source if source.is_human() => InputSource::Ai(TEAMMATE_DIFFICULTY),
+                _ => InputSource::Idle,
+            };
+            commands
+                .spawn_bundle(TransformBundle::from_transform(
+                    Transform::from_translation(position),
+                ))
+                .insert(team)
+                .insert(role)
+                .insert(PlayerId(id))
+                .insert(HomePosition(position))
+                .insert(input_source)
+                .insert(PlayerInput::default())
+                .insert(SwitchHeld::default())
+                .insert(KickCharge::default())
+                .insert(AiMemory::new(config.seed.wrapping_add(id as u64)))
+                .insert(PreviousPosition(position))
+                .insert(Velocity { x: 0.0, y: 0.0 })
+                .insert(Radius(PLAYER_RADIUS))
+                .insert(Mass(config.physics.player_mass))
+                .insert(Restitution(config.physics.player_restitution))
+                .insert(MatchEntity);
+            id += 1;
+        }
+    }
+}
+
+fn spawn_ball_system(mut commands: Commands, config: Res<MatchConfig>) {
+    // Spawn ball in the center of the pitch.
+    commands
+        .spawn_bundle(TransformBundle::from_transform(
+            Transform::from_translation(Vec3::new(0.0, 0.0, 5.0)),
+        ))
+        .insert(Ball)
+        .insert(PreviousPosition(Vec3::new(0.0, 0.0, 5.0)))
+        .insert(Velocity { x: 0.0, y: 0.0 })
+        .insert(Radius(BALL_RADIUS))
+        .insert(Mass(config.physics.ball_mass))
+        .insert(Restitution(config.physics.ball_restitution))
+        .insert(MatchEntity);
+}
+
+fn spawn_posts_system(mut commands: Commands, config: Res<MatchConfig>) {
+    for post in config.pitch.posts.iter() {
+        commands
+            .spawn_bundle(TransformBundle::from_transform(
+                Transform::from_translation(post.extend(5.0)),
+            ))
+            .insert(Post)
+            .insert(Velocity { x: 0.0, y: 0.0 })
+            .insert(Radius(config.pitch.post_radius))
+            .insert(Mass::STATIC)
+            .insert(MatchEntity);
+    }
+}
+
+// Removes everything spawned for the match when leaving it, e.g. for a
+// rematch or going back to the menu. Pausing the match doesn't leave it.
+fn despawn_match_system(mut commands: Commands, query: Query<Entity, With<MatchEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Slows down a velocity component, as the friction does.
+pub fn apply_friction(velocity: f32, friction: f32) -> f32 {
+    if velocity > 0. {
+        (velocity - friction).max(-MAX_SPEED)
+    } else if velocity < 0. {
+        (velocity + friction).min(MAX_SPEED)
+    } else {
+        velocity
+    }
+}
+
+// Speeds up a velocity component in the direction of the input, up to
+// MAX_SPEED scaled by the input (e.g. by the tilt of a stick). A player faster
+// than that, or without input along that axis, slows down.
+fn accelerate(velocity: f32, input: f32) -> f32 {
+    let max_speed = MAX_SPEED * input.abs();
+    if input > 0. {
+        if velocity < max_speed {
+            (velocity + 0.1 * input).min(max_speed)
+        } else {
+            (velocity - PLAYER_FRICTION).max(max_speed)
+        }
+    } else if input < 0. {
+        if velocity > -max_speed {
+            (velocity + 0.1 * input).max(-max_speed)
+        } else {
+            (velocity + PLAYER_FRICTION).min(-max_speed)
+        }
+    } else {
+        apply_friction(velocity, PLAYER_FRICTION)
+    }
+}
+
+// Slows down the ball.
+pub fn control_ball_velocity(
+    config: Res<MatchConfig>,
+    mut query: Query<&mut Velocity, With<Ball>>,
+) {
+    let friction = config.physics.ball_friction;
+    for mut velocity in query.iter_mut() {
+        velocity.x = apply_friction(velocity.x, friction);
+        velocity.y = apply_friction(velocity.y, friction);
+    }
+}
+
+// Hands the control of a human player over to a teammate when the player
+// presses switch: to the teammate in the direction the player moves, or else
+// to the one closest to the ball. The AI takes over the player left behind.
+pub fn switch_player_system(
+    query_ball: Query<&Transform, With<Ball>>,
+    mut query: Query<(
+        &PlayerId,
+        &Team,
+        &Transform,
+        &PlayerInput,
+        &mut InputSource,
+        &mut SwitchHeld,
+    )>,
+) {
+    let ball = match query_ball.iter().next() {
+        Some(transform) => transform.translation.truncate(),
+        None => return,
+    };
+    let mut players: Vec<_> = query.iter_mut().collect();
+    players.sort_by_key(|(id, ..)| **id);
+
+    // Find every press first: the swaps below move SwitchHeld between the
+    // players, so a player reached after a swap would see the wrong one.
+    let mut pressed = Vec::new();
+    for (i, (_, _, _, input, source, held)) in players.iter_mut().enumerate() {
+        if source.is_human() {
+            if input.switch && !held.0 {
+                pressed.push(i);
+            }
+            held.0 = input.switch;
+        }
+    }
+
+    // Players that already changed hands in this step.
+    let mut switched = Vec::new();
+    for i in pressed {
+        if switched.contains(&i) {
+            continue;
+        }
+        let (_, team, transform, input, ..) = &players[i];
+        let (team, position) = (**team, transform.translation.truncate());
+        let direction = input.movement.normalize_or_zero();
+        let teammates = || {
+            players
+                .iter()
+                .enumerate()
+                .filter(move |(j, (_, other, ..))| *j != i && **other == team)
+                .map(|(j, (_, _, transform, ..))| (j, transform.translation.truncate()))
+        };
+        // The distances are compared with total_cmp and ties go to the lower
+        // id, to always pick the same teammate.
+        let closest = |candidates: Vec<(usize, Vec2)>, to: Vec2| {
+            candidates
+                .into_iter()
+                .min_by(|(_, a), (_, b)| a.distance(to).total_cmp(&b.distance(to)))
+                .map(|(j, _)| j)
+        };
+        let in_direction = teammates()
+            .filter(|(_, other)| {
+                (*other - position).normalize_or_zero().dot(direction) >= SWITCH_ALIGNMENT
+            })
+            .collect();
+        let target = match closest(in_direction, position) {
+            Some(j) => Some(j),
+            None => closest(teammates().collect(), ball),
+        };
+
+        if let Some(j) = target {
+            let (low, high) = (i.min(j), i.max(j));
+            let (head, tail) = players.split_at_mut(high);
+            let (player1, player2) = (&mut head[low], &mut tail[0]);
+            std::mem::swap(&mut *player1.4, &mut *player2.4);
+            // The new player starts with switch held, like the old one.
+            std::mem::swap(&mut *player1.5, &mut *player2.5);
+            switched.extend([i, j]);
+        }
+    }
+}
+
+// Changes velocity of the players, based on their input.
+pub fn player_movement_system(mut query: Query<(&PlayerInput, &mut Velocity)>) {
+    for (input, mut velocity) in query.iter_mut() {
+        velocity.x = accelerate(velocity.x, input.movement.x);
+        velocity.y = accelerate(velocity.y, input.movement.y);
+    }
+}
+
+// Changes the position of the entities, based on their velocity.
+pub fn movement_system(
+    mut query: Query<(&Velocity, &mut Transform, Option<&mut PreviousPosition>)>,
+) {
+    for (velocity, mut transform, previous) in query.iter_mut() {
+        if let Some(mut previous) = previous {
+            previous.0 = transform.translation;
+        }
+        let translation = &mut transform.translation;
+        translation.x += velocity.x;
+        translation.y += velocity.y;
+    }
+}
+
+// Makes human controlled goalkeepers bigger inside their goal area, if the
+// pitch wants them to be.
+pub fn goalkeeper_radius_system(
+    config: Res<MatchConfig>,
+    geometry: Res<PitchGeometry>,
+    mut query: Query<(&Role, &Team, &InputSource, &Transform, &mut Radius)>,
+) {
+    let goalkeeper_radius = match config.physics.goalkeeper_radius {
+        Some(radius) => radius,
+        None => return,
+    };
+    for (role, team, source, transform, mut radius) in query.iter_mut() {
+        if *role != Role::Goalkeeper {
+            continue;
+        }
+        let human = source.is_human();
+        let position = transform.translation.truncate();
+        let in_area = geometry
+            .goals
+            .iter()
+            .any(|goal| goal.defending == *team && goal.in_area(position));
+        let new_radius = if human && in_area {
+            goalkeeper_radius
+        } else {
+            PLAYER_RADIUS
+        };
+        if radius.0 != new_radius {
+            radius.0 = new_radius;
+        }
+    }
+}
+
+// Charges the kicks of the players holding kick. When a player releases it,
+// kicks the ball away from the player if it is close enough, with a strength
+// that grows with the charge, and starts the cooldown.
+pub fn kick_system(
+    rules: Res<MatchRules>,
+    mut query_players: Query<(
+        &PlayerId,
+        &Transform,
+        &Radius,
+        &PlayerInput,
+        &mut KickCharge,
+    )>,
+    mut query_ball: Query<(&mut Velocity, &Transform, &Radius), With<Ball>>,
+) {
+    let mut players: Vec<_> = query_players.iter_mut().collect();
+    players.sort_by_key(|(id, ..)| **id);
+
+    for (_, transform, radius, input, charge) in players.iter_mut() {
+        if charge.cooldown > 0 {
+            charge.cooldown -= 1;
+            continue;
+        }
+        if input.kick {
+            charge.ticks = (charge.ticks + 1).min(KICK_CHARGE_TICKS);
+            continue;
+        }
+        if charge.ticks == 0 {
+            continue;
+        }
+
+        let strength = KICK_STRENGTH * charge.power().max(MIN_KICK_POWER);
+        **charge = KickCharge {
+            ticks: 0,
+            cooldown: KICK_COOLDOWN_TICKS,
+        };
+        for (mut velocity_ball, transform_ball, radius_ball) in query_ball.iter_mut() {
+            let player_ball_distance = transform.translation.distance(transform_ball.translation);
+            if player_ball_distance < radius.0 + radius_ball.0 + KICK_MARGIN {
+                // Push the ball away from the player. No trigonometry here, as
+                // atan2/sin/cos may give slightly different results on other machines.
+                let normal = (transform_ball.translation - transform.translation)
+                    .truncate()
+                    .normalize_or_zero();
+                let direction = match rules.kick_control {
+                    KickControl::Classic => normal,
+                    KickControl::Aimed => {
+                        let aimed = (normal * (1.0 - AIM_WEIGHT)
+                            + input.movement.normalize_or_zero() * AIM_WEIGHT)
+                            .normalize_or_zero();
+                        // Moving straight into the player cancels the aim
+                        // out, and it must not send the ball through the player.
+                        if aimed.dot(normal) > 0.0 {
+                            aimed
+                        } else {
+                            normal
+                        }
+                    }
+                };
+                velocity_ball.x += strength * direction.x;
+                velocity_ball.y += strength * direction.y;
+            }
+        }
+    }
+}
+
+// Handles collision between bodies and the walls of the pitch. The players
+// are stopped by the goal lines too.
+pub fn edge_collision_system(
+    geometry: Res<PitchGeometry>,
+    config: Res<MatchConfig>,
+    mut query: Query<(
+        &mut Velocity,
+        &mut Transform,
+        &Radius,
+        &PreviousPosition,
+        Option<&Ball>,
+    )>,
+) {
+    let restitution = config.physics.wall_restitution;
+    for (mut velocity, mut transform, radius, previous, ball) in query.iter_mut() {
+        let radius = radius.0;
+
+        // A fast body could get through a wall within one step, so first find
+        // where its path hit a wall, bounce it off there and let it move on
+        // for the rest of the step.
+        let start = previous.0.truncate();
+        let motion = transform.translation.truncate() - start;
+        let hit = geometry
+            .barriers(ball.is_some())
+            .filter_map(|segment| sweep_circle_segment(start, motion, radius, segment.a, segment.b))
+            .min_by(|(t1, _), (t2, _)| t1.total_cmp(t2));
+        if let Some((t, normal)) = hit {
+            let v = reflect(Vec2::new(velocity.x, velocity.y), normal, restitution);
+            velocity.x = v.x;
+            velocity.y = v.y;
+            let position = start + motion * t + v * (1.0 - t);
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+            continue;
+        }
+
+        // Push the body out of the walls it overlaps and bounce it off them,
+        // unless it already moves away.
+        let mut position = transform.translation.truncate();
+        for segment in geometry.barriers(ball.is_some()) {
+            let delta = position - segment.closest_point(position);
+            let distance = delta.length();
+            if distance >= radius {
+                continue;
+            }
+            // A body exactly on the wall is pushed towards the centre.
+            let normal = if distance > 0.0 {
+                delta / distance
+            } else {
+                (-position).normalize_or_zero()
+            };
+            position += normal * (radius - distance);
+
+            let v = Vec2::new(velocity.x, velocity.y);
+            if v.dot(normal) < 0.0 {
+                let v = reflect(v, normal, restitution);
+                velocity.x = v.x;
+                velocity.y = v.y;
+            }
+        }
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
+
+// Bounces a velocity off a wall with the given normal, keeping the part along
+// the wall and restitution times the part across it.
+fn reflect(velocity: Vec2, normal: Vec2, restitution: f32) -> Vec2 {
+    velocity - (1.0 + restitution) * velocity.dot(normal) * normal
+}
+
+// Check if there was a goal.
+// If there was, update the score.
+pub fn goal_system(
+    mut query_ball: Query<(&mut Velocity, &mut Transform, &mut PreviousPosition, &Ball)>,
+    mut query_players: Query<
+        (
+            &mut Velocity,
+            &mut Transform,
+            &mut PreviousPosition,
+            &HomePosition,
+        ),
+        Without<Ball>,
+    >,
+    geometry: Res<PitchGeometry>,
+    mut score: ResMut<Score>,
+    mut kickoff: ResMut<Kickoff>,
+) {
+    // Get tuple from query
+    let (mut velocity_ball, mut transform_ball, mut previous_ball, _) =
+        query_ball.iter_mut().next().unwrap();
+
+    let position = transform_ball.translation.truncate();
+    if let Some(goal) = geometry.goals.iter().find(|goal| goal.is_scored(position)) {
+        match goal.defending.opponent() {
+            Team::Red => score.red += 1,
+            Team::Blue => score.blue += 1,
+        }
+        // The conceding team kicks off.
+        *kickoff = Kickoff::new(goal.defending);
+
+        let centre = Vec3::new(0., 0., transform_ball.translation.z);
+        reset_body(
+            &mut velocity_ball,
+            &mut transform_ball,
+            &mut previous_ball,
+            centre,
+        );
+        for (mut velocity, mut transform, mut previous, home) in query_players.iter_mut() {
+            reset_body(&mut velocity, &mut transform, &mut previous, home.0);
+        }
+    }
+}
+
+// Teleports a body to the given position and stops it.
+pub fn reset_body(
+    velocity: &mut Velocity,
+    transform: &mut Transform,
+    previous: &mut PreviousPosition,
+    position: Vec3,
+) {
+    velocity.x = 0.;
+    velocity.y = 0.;
+    transform.translation = position;
+    // Teleport, so don't interpolate from the old position.
+    previous.0 = position;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Accelerates from standing still for long enough to reach top speed.
+    fn top_speed(input: f32) -> f32 {
+        (0..200).fold(0.0, |velocity, _| accelerate(velocity, input))
+    }
+
+    #[test]
+    fn speed_is_scaled_by_input() {
+        assert_eq!(top_speed(1.0), MAX_SPEED);
+        assert_eq!(top_speed(-1.0), -MAX_SPEED);
+        assert_eq!(top_speed(0.5), MAX_SPEED * 0.5);
+    }
+
+    #[test]
+    fn smaller_input_slows_down_to_its_speed() {
+        let velocity = (0..200).fold(MAX_SPEED, |velocity, _| accelerate(velocity, 0.5));
+        assert_eq!(velocity, MAX_SPEED * 0.5);
+    }
+
+    // A player touching the ball from the left, stepped with only
+    // kick_system.
+    struct KickTest {
+        world: World,
+        stage: SystemStage,
+        player: Entity,
+        ball: Entity,
+    }
+
+    impl KickTest {
+        fn new() -> Self {
+            let mut world = World::new();
+            world.insert_resource(MatchRules::default());
+            let player = world
+                .spawn()
+                .insert(PlayerId(0))
+                .insert(Transform::default())
+                .insert(Radius(PLAYER_RADIUS))
+                .insert(PlayerInput::default())
+                .insert(KickCharge::default())
+                .id();
+            let ball = world
+                .spawn()
+                .insert(Ball)
+                .insert(Velocity { x: 0.0, y: 0.0 })
+                .insert(Transform::from_xyz(PLAYER_RADIUS + BALL_RADIUS, 0.0, 0.0))
+                .insert(Radius(BALL_RADIUS))
+                .id();
+            Self {
+                world,
+                stage: SystemStage::single(kick_system),
+                player,
+                ball,
+            }
+        }
+
+        fn step(&mut self, kick: bool) {
+            self.world
+                .get_mut::<PlayerInput>(self.player)
+                .expect("the player has no PlayerInput")
+                .kick = kick;
+            self.stage.run(&mut self.world);
+        }
+
+        fn charge(&self) -> KickCharge {
+            *self
+                .world
+                .get::<KickCharge>(self.player)
+                .expect("the player has no KickCharge")
+        }
+
+        fn ball_speed(&self) -> f32 {
+            let velocity = self
+                .world
+                .get::<Velocity>(self.ball)
+                .expect("the ball has no Velocity");
+            Vec2::new(velocity.x, velocity.y).length()
+        }
+    }
+
+    #[test]
+    fn held_kick_is_charged_and_taken_once_on_release() {
+        let mut test = KickTest::new();
+        for _ in 0..15 {
+            test.step(true);
+        }
+        assert_eq!(test.charge().ticks, 15);
+        assert_eq!(test.ball_speed(), 0.0);
+
+        test.step(false);
+        let power = 15.0 / KICK_CHARGE_TICKS as f32;
+        assert!((test.ball_speed() - KICK_STRENGTH * power).abs() < 1e-5);
+        assert_eq!(test.charge().ticks, 0);
+
+        // Nothing more happens while kick stays released.
+        let speed = test.ball_speed();
+        for _ in 0..KICK_COOLDOWN_TICKS * 2 {
+            test.step(false);
+        }
+        assert_eq!(test.ball_speed(), speed);
+    }
+
+    #[test]
+    fn tapped_kick_has_minimum_power() {
+        let mut test = KickTest::new();
+        test.step(true);
+        test.step(false);
+        assert!((test.ball_speed() - KICK_STRENGTH * MIN_KICK_POWER).abs() < 1e-5);
+    }
+
+    #[test]
+    fn cooldown_blocks_charging() {
+        let mut test = KickTest::new();
+        test.step(true);
+        test.step(false);
+        assert_eq!(test.charge().cooldown, KICK_COOLDOWN_TICKS);
+
+        for _ in 0..KICK_COOLDOWN_TICKS {
+            test.step(true);
+            assert_eq!(test.charge().ticks, 0);
+        }
+        test.step(true);
+        assert_eq!(test.charge().ticks, 1);
+    }
+}
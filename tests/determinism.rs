@@ -0,0 +1,78 @@
+use bevy::prelude::*;
+use rustball::determinism::state_hash;
+use rustball::input::{InputSource, InputSources, PlayerInput};
+use rustball::simulation::{step_simulation, Ball, MatchConfig, PlayerId, Velocity};
+use rustball::{GameState, SimulationPlugin};
+
+const TICKS: u64 = 1200;
+
+// Moves in one of eight directions for half a second at a time and kicks
+// every now and then, differently for every player.
+fn scripted_input(tick: u64, id: u32) -> PlayerInput {
+    let directions = [
+        Vec2::new(1.0, 0.0),
+        Vec2::new(1.0, 1.0),
+        Vec2::new(0.0, 1.0),
+        Vec2::new(-1.0, 1.0),
+        Vec2::new(-1.0, 0.0),
+        Vec2::new(-1.0, -1.0),
+        Vec2::new(0.0, -1.0),
+        Vec2::new(1.0, -1.0),
+    ];
+    let id = id as u64;
+    PlayerInput {
+        movement: directions[((tick / 30 + id * 3) % 8) as usize],
+        kick: (tick + id * 7) % 40 < 25,
+        switch: false,
+    }
+}
+
+// Plays a match with the given seed and returns the state hash after every
+// step, and whether the ball was ever kicked.
+fn run(seed: u64) -> (Vec<u64>, bool) {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(MatchConfig {
+            seed,
+            team_size: 2,
+            ..Default::default()
+        })
+        .insert_resource(InputSources {
+            red: InputSource::External,
+            blue: InputSource::External,
+        })
+        .add_state(GameState::InGame)
+        .add_plugin(SimulationPlugin);
+
+    let mut hashes = Vec::new();
+    let mut ball_moved = false;
+    for tick in 0..TICKS {
+        let mut query = app
+            .world
+            .query::<(&PlayerId, &InputSource, &mut PlayerInput)>();
+        for (id, source, mut input) in query.iter_mut(&mut app.world) {
+            if *source == InputSource::External {
+                *input = scripted_input(tick, id.0);
+            }
+        }
+        step_simulation(&mut app, 1);
+        hashes.push(state_hash(&mut app.world));
+        let mut query_ball = app.world.query_filtered::<&Velocity, With<Ball>>();
+        ball_moved |= query_ball
+            .iter(&app.world)
+            .any(|velocity| velocity.x != 0.0 || velocity.y != 0.0);
+    }
+    (hashes, ball_moved)
+}
+
+#[test]
+fn same_seed_and_inputs_give_same_state() {
+    let (hashes1, ball_moved) = run(42);
+    let (hashes2, _) = run(42);
+    assert_eq!(hashes1.len(), hashes2.len());
+    for (tick, (hash1, hash2)) in hashes1.iter().zip(hashes2.iter()).enumerate() {
+        assert_eq!(hash1, hash2, "the runs differ after step {}", tick + 1);
+    }
+    // The players really played, not just stood still.
+    assert!(ball_moved);
+}
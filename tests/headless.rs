@@ -0,0 +1,60 @@
+use bevy::ecs::event::{Events, ManualEventReader};
+use bevy::prelude::*;
+use rustball::ai::Difficulty;
+use rustball::input::{InputSource, InputSources};
+use rustball::rules::{MatchEnded, MatchPhase, MatchRules};
+use rustball::simulation::{step_simulation, MatchConfig, Score, SimulationTick, Team};
+use rustball::{GameState, SimulationPlugin};
+
+// Steps before the test gives up on the match ending.
+const MAX_TICKS: u64 = 60 * 60 * 5;
+
+// A whole match between two AI teams, with two halves, runs without any
+// window, rendering or assets.
+#[test]
+fn match_plays_to_the_end_under_minimal_plugins() {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(MatchConfig {
+            seed: 7,
+            team_size: 2,
+            rules: MatchRules {
+                score_limit: None,
+                time_limit: Some(30),
+                golden_goal: false,
+                draw_allowed: true,
+                halves: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert_resource(InputSources {
+            red: InputSource::Ai(Difficulty::Hard),
+            blue: InputSource::Ai(Difficulty::Easy),
+        })
+        .add_state(GameState::InGame)
+        .add_plugin(SimulationPlugin);
+
+    let mut reader = ManualEventReader::<MatchEnded>::default();
+    let mut result = None;
+    while result.is_none() {
+        step_simulation(&mut app, 60);
+        let events = app.world.resource::<Events<MatchEnded>>();
+        result = reader.iter(events).next().map(|event| event.winner);
+        let tick = app.world.resource::<SimulationTick>().0;
+        assert!(tick < MAX_TICKS, "the match didn't end");
+    }
+
+    assert_eq!(*app.world.resource::<MatchPhase>(), MatchPhase::Ended);
+    let score = app.world.resource::<Score>();
+    match result.expect("Something went wrong!") {
+        Some(Team::Red) => assert!(score.red > score.blue),
+        Some(Team::Blue) => assert!(score.blue > score.red),
+        None => assert_eq!(score.red, score.blue),
+    }
+
+    // Nothing happens after the end.
+    let tick = app.world.resource::<SimulationTick>().0;
+    step_simulation(&mut app, 60);
+    assert_eq!(app.world.resource::<SimulationTick>().0, tick);
+}
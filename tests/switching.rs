@@ -0,0 +1,67 @@
+use bevy::prelude::*;
+use rustball::input::{InputSource, InputSources, PlayerInput};
+use rustball::simulation::{step_simulation, MatchConfig, PlayerId, Team};
+use rustball::{GameState, SimulationPlugin};
+
+// Ids of the red players controlled from outside.
+fn controlled(app: &mut App) -> Vec<u32> {
+    let mut query = app.world.query::<(&PlayerId, &Team, &InputSource)>();
+    let mut ids: Vec<_> = query
+        .iter(&app.world)
+        .filter(|(_, team, source)| **team == Team::Red && **source == InputSource::External)
+        .map(|(id, ..)| id.0)
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+// Holds switch for the controlled red player for the given number of steps.
+fn hold_switch(app: &mut App, steps: u32) {
+    for _ in 0..steps {
+        let mut query = app.world.query::<(&InputSource, &mut PlayerInput)>();
+        for (source, mut input) in query.iter_mut(&mut app.world) {
+            if *source == InputSource::External {
+                input.switch = true;
+            }
+        }
+        step_simulation(app, 1);
+    }
+}
+
+fn release_switch(app: &mut App) {
+    let mut query = app.world.query::<(&InputSource, &mut PlayerInput)>();
+    for (source, mut input) in query.iter_mut(&mut app.world) {
+        if *source == InputSource::External {
+            input.switch = false;
+        }
+    }
+    step_simulation(app, 1);
+}
+
+// Holding switch down hands the control over once and keeps it there, and
+// the next press hands it back.
+#[test]
+fn holding_switch_switches_once() {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(MatchConfig {
+            team_size: 2,
+            ..Default::default()
+        })
+        .insert_resource(InputSources {
+            red: InputSource::External,
+            blue: InputSource::Idle,
+        })
+        .add_state(GameState::InGame)
+        .add_plugin(SimulationPlugin);
+    step_simulation(&mut app, 1);
+    assert_eq!(controlled(&mut app), vec![0]);
+
+    // Red has the players 0 and 1, the goalkeeper.
+    hold_switch(&mut app, 10);
+    assert_eq!(controlled(&mut app), vec![1]);
+
+    release_switch(&mut app);
+    hold_switch(&mut app, 10);
+    assert_eq!(controlled(&mut app), vec![0]);
+}